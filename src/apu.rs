@@ -0,0 +1,52 @@
+/* https://www.nesdev.org/wiki/APU_registers
+* Only the register file so far, nothing is synthesised yet. $4000-$4013 set up the pulse,
+* triangle, noise and DMC channels, $4015 enables channels and reports their status, and
+* $4017 configures the frame counter. Apart from $4015 every register is write only.
+*/
+
+pub struct Apu {
+    channel_registers: [u8; 0x14],
+    channels_enabled: u8,
+    frame_counter: u8,
+    frame_interrupt: bool,
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Apu {
+            channel_registers: [0; 0x14],
+            channels_enabled: 0,
+            frame_counter: 0,
+            frame_interrupt: false,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x4000..=0x4013 => self.channel_registers[(address - 0x4000) as usize] = value,
+            0x4015 => self.channels_enabled = value & 0x1F,
+            0x4017 => {
+                self.frame_counter = value;
+                // Setting the interrupt inhibit flag clears a pending frame interrupt
+                if value & 0x40 != 0 {
+                    self.frame_interrupt = false;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // $4015. Reading it acknowledges the frame interrupt. Bit 5 isn't driven, the caller
+    // fills it in from open bus.
+    pub fn read_status(&mut self) -> u8 {
+        let status = self.peek_status();
+        self.frame_interrupt = false;
+        status
+    }
+
+    // $4015 without acknowledging anything
+    pub fn peek_status(&self) -> u8 {
+        // Length counters aren't emulated yet so every channel reports as silent
+        (self.frame_interrupt as u8) << 6
+    }
+}
@@ -0,0 +1,263 @@
+/* https://www.nesdev.org/wiki/INES#Flags_6
+* Cartridges with a battery keep their work RAM powered while the console is off, which
+* is how games save. Here that RAM lives in a .sav file holding nothing but its contents,
+* the same format other emulators use. The file is read when the cartridge is inserted and
+* written back when it's dropped. A frontend can also call flush, say between frames, to
+* save as the game goes and to see any errors, but there's no run loop doing that yet.
+* Writes to the RAM only mark it dirty, so flush is cheap when nothing changed.
+*
+* Boards that save to an EEPROM instead, like Bandai's FCG boards, hand it over through
+* battery_ram the same way. None of those are emulated yet.
+*/
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use super::{Mapper, Mirroring};
+
+// Where the save for a ROM goes, next to it or in saves if given, named after the ROM
+pub fn save_path(rom: &Path, saves: Option<&Path>) -> PathBuf {
+    let mut name = rom.file_stem().unwrap_or(rom.as_os_str()).to_os_string();
+    name.push(".sav");
+    let directory = saves.or(rom.parent()).unwrap_or(Path::new(""));
+    directory.join(name)
+}
+
+// Wraps a board and keeps its battery RAM in a file
+pub struct Battery {
+    mapper: Box<dyn Mapper>,
+    path: PathBuf,
+    // What's in the file, to skip writing it when nothing changed
+    saved: Vec<u8>,
+    // Set by writes to the RAM since the last flush
+    dirty: bool,
+}
+
+impl Battery {
+    // Fills the board's RAM from the save file, if there is one. A file of the wrong size
+    // still has as much of it loaded as fits, and a copy is kept with .bak added to the
+    // name since it's about to be overwritten. A board without any RAM never writes the
+    // file, so it's left alone.
+    pub fn new(mut mapper: Box<dyn Mapper>, path: PathBuf) -> io::Result<Self> {
+        let saved = match fs::read(&path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(error) => return Err(error),
+        };
+        if let Some(ram) = mapper.battery_ram() {
+            if !ram.is_empty() && !saved.is_empty() && saved.len() != ram.len() {
+                let mut backup = path.clone().into_os_string();
+                backup.push(".bak");
+                fs::copy(&path, backup)?;
+            }
+            let length = saved.len().min(ram.len());
+            ram[..length].copy_from_slice(&saved[..length]);
+        }
+        Ok(Battery {
+            mapper,
+            path,
+            saved,
+            dirty: false,
+        })
+    }
+}
+
+impl Drop for Battery {
+    // There's no one to report an error to here, frontends that care flush first
+    fn drop(&mut self) {
+        self.dirty = true;
+        let _ = self.flush();
+    }
+}
+
+impl Mapper for Battery {
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        self.mapper.cpu_read(address)
+    }
+
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        self.mapper.cpu_peek(address)
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        // Boards like the MMC5 can map their RAM over the ROM as well
+        self.dirty |= address >= 0x6000;
+        self.mapper.cpu_write(address, value)
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.mapper.ppu_read(address)
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        self.mapper.ppu_peek(address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.mapper.ppu_write(address, value)
+    }
+
+    fn ppu_address_bus(&mut self, address: u16) {
+        self.mapper.ppu_address_bus(address)
+    }
+
+    fn nametable_read(&mut self, address: u16) -> Option<u8> {
+        self.mapper.nametable_read(address)
+    }
+
+    fn nametable_write(&mut self, address: u16, value: u8) -> bool {
+        self.mapper.nametable_write(address, value)
+    }
+
+    fn ppu_register_write(&mut self, address: u16, value: u8) {
+        self.mapper.ppu_register_write(address, value)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mapper.mirroring()
+    }
+
+    fn irq(&self) -> bool {
+        self.mapper.irq()
+    }
+
+    fn cpu_clock(&mut self) {
+        self.mapper.cpu_clock()
+    }
+
+    fn battery_ram(&mut self) -> Option<&mut [u8]> {
+        self.mapper.battery_ram()
+    }
+
+    // Writes the RAM to the save file if it changed since the last time. Games rewrite
+    // the same values all the time, so being dirty only means it's worth comparing.
+    fn flush(&mut self) -> io::Result<()> {
+        if !std::mem::take(&mut self.dirty) {
+            return Ok(());
+        }
+        let Some(ram) = self.mapper.battery_ram() else {
+            return Ok(());
+        };
+        if ram.is_empty() || ram == self.saved.as_slice() {
+            return Ok(());
+        }
+        // Written to a temporary file first so a crash halfway through can't lose the save
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        let written = fs::write(&temporary, &*ram).and_then(|_| fs::rename(&temporary, &self.path));
+        match written {
+            Ok(()) => self.saved = ram.to_vec(),
+            // Still dirty, so the next flush tries again
+            Err(_) => self.dirty = true,
+        }
+        written
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    use super::{super::Cartridge, save_path, Mapper};
+
+    // An MMC1 board with 8K of PRG RAM
+    fn cartridge(battery: bool) -> Cartridge {
+        let flags = if battery { 0x12 } else { 0x10 };
+        let mut bytes = vec![
+            b'N', b'E', b'S', 0x1A, 2, 1, flags, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        bytes.resize(16 + 0x8000 + 0x2000, 0);
+        Cartridge::from_bytes(&bytes).unwrap()
+    }
+
+    // An empty directory of its own for each test
+    fn directory(test: &str) -> PathBuf {
+        let name = format!("battery-{test}-{}", std::process::id());
+        let directory = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn insert(battery: bool, directory: &Path) -> Box<dyn Mapper> {
+        let rom = directory.join("game.nes");
+        cartridge(battery)
+            .into_mapper_with_save(&rom, None)
+            .unwrap()
+    }
+
+    #[test]
+    fn paths() {
+        let rom = Path::new("roms/Game.v1.nes");
+        assert_eq!(save_path(rom, None), Path::new("roms/Game.v1.sav"));
+        let saves = Path::new("saves");
+        assert_eq!(save_path(rom, Some(saves)), Path::new("saves/Game.v1.sav"));
+    }
+
+    #[test]
+    fn round_trip() {
+        let directory = directory("round-trip");
+        let save = directory.join("game.sav");
+        let mut mapper = insert(true, &directory);
+        // Nothing is written until the frontend flushes
+        mapper.cpu_write(0x6000, 0x42);
+        assert!(!save.exists());
+        mapper.flush().unwrap();
+        assert_eq!(fs::read(&save).unwrap()[0], 0x42);
+        // And the last changes when the cartridge is removed
+        mapper.cpu_write(0x7FFF, 0x43);
+        drop(mapper);
+        let saved = fs::read(&save).unwrap();
+        assert_eq!((saved.len(), saved[0x1FFF]), (0x2000, 0x43));
+        let mapper = insert(true, &directory);
+        assert_eq!(mapper.cpu_peek(0x6000), Some(0x42));
+        assert_eq!(mapper.cpu_peek(0x7FFF), Some(0x43));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    // A save of the wrong size is loaded as far as it goes, and kept in a .bak
+    #[test]
+    fn size_mismatch() {
+        let directory = directory("size-mismatch");
+        let save = directory.join("game.sav");
+        fs::write(&save, [1, 2, 3]).unwrap();
+        let mapper = insert(true, &directory);
+        assert_eq!(mapper.cpu_peek(0x6002), Some(3));
+        assert_eq!(fs::read(directory.join("game.sav.bak")).unwrap(), [1, 2, 3]);
+        drop(mapper);
+        assert_eq!(fs::read(&save).unwrap().len(), 0x2000);
+        // Without any RAM the file isn't touched, so there's nothing to back up
+        fs::write(&save, [1, 2, 3]).unwrap();
+        fs::remove_file(directory.join("game.sav.bak")).unwrap();
+        let mut cartridge = cartridge(true);
+        cartridge.header.prg_ram_size = 0;
+        cartridge.header.prg_nvram_size = 0;
+        let rom = directory.join("game.nes");
+        drop(cartridge.into_mapper_with_save(&rom, None).unwrap());
+        assert!(!directory.join("game.sav.bak").exists());
+        assert_eq!(fs::read(&save).unwrap(), [1, 2, 3]);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn missing_file() {
+        let directory = directory("missing-file");
+        let mut mapper = insert(true, &directory);
+        assert_eq!(mapper.cpu_peek(0x6000), Some(0));
+        // RAM that was never written isn't saved
+        mapper.flush().unwrap();
+        assert!(!directory.join("game.sav").exists());
+        // Neither is the RAM of a board without a battery
+        let mut mapper = insert(false, &directory);
+        mapper.cpu_write(0x6000, 0x42);
+        mapper.flush().unwrap();
+        drop(mapper);
+        assert!(!directory.join("game.sav").exists());
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
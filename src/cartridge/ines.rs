@@ -0,0 +1,424 @@
+/* https://www.nesdev.org/wiki/INES
+* https://www.nesdev.org/wiki/NES_2.0
+* A 16 byte header, an optional 512 byte trainer, then PRG ROM and CHR ROM.
+*
+* Bytes 0-3  "NES" followed by $1A
+* Byte 4     PRG ROM size in 16K units
+* Byte 5     CHR ROM size in 8K units, 0 means the board has CHR RAM
+* Byte 6     Mapper low nibble, four-screen, trainer, battery and mirroring flags
+* Byte 7     Mapper middle nibble, NES 2.0 identifier and console type
+*
+* In NES 2.0 the rest of the header gives the upper bits of the mapper number and the ROM
+* sizes, the submapper, the sizes of every kind of RAM, and the timing of the console.
+*/
+
+use std::{error::Error, fmt, io};
+
+use super::{Cartridge, Mirroring};
+
+const MAGIC: [u8; 4] = *b"NES\x1A";
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+const PRG_ROM_UNIT: usize = 0x4000;
+const CHR_ROM_UNIT: usize = 0x2000;
+
+#[derive(Debug)]
+pub enum RomError {
+    Io(io::Error),
+    // The file doesn't start with "NES" followed by $1A
+    NotINes,
+    // The header says there's more data than the file has
+    Truncated { expected: usize, found: usize },
+    NoPrgRom,
+    // An NES 2.0 ROM size given in exponent notation that's too big to load
+    TooLarge,
+    // No board is emulated for this mapper number
+    UnsupportedMapper(u16),
+    // The battery save couldn't be read
+    Save(io::Error),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::Io(error) => write!(f, "couldn't read the ROM: {error}"),
+            RomError::NotINes => write!(f, "not an iNES file"),
+            RomError::Truncated { expected, found } => write!(
+                f,
+                "the ROM is truncated, expected {expected} bytes but found {found}"
+            ),
+            RomError::NoPrgRom => write!(f, "the ROM has no PRG ROM"),
+            RomError::TooLarge => write!(f, "the ROM is too large"),
+            RomError::UnsupportedMapper(mapper) => write!(f, "mapper {mapper} isn't supported"),
+            RomError::Save(error) => write!(f, "couldn't read the save file: {error}"),
+        }
+    }
+}
+
+impl Error for RomError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RomError::Io(error) | RomError::Save(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RomError {
+    fn from(error: io::Error) -> Self {
+        RomError::Io(error)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    INes,
+    Nes20,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    Playchoice10,
+    // NES 2.0 byte 13, clones and other hardware built around the 6502
+    Extended(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    // Works on either
+    MultiRegion,
+    Dendy,
+}
+
+#[derive(Clone, Debug)]
+pub struct Header {
+    pub format: Format,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    // RAM sizes in bytes, nvram is battery backed
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    pub trainer: bool,
+    pub console: ConsoleType,
+    pub timing: Timing,
+}
+
+impl Header {
+    pub fn parse(bytes: &[u8]) -> Result<Header, RomError> {
+        if bytes.len() < MAGIC.len() || bytes[..MAGIC.len()] != MAGIC {
+            return Err(RomError::NotINes);
+        }
+        if bytes.len() < HEADER_SIZE {
+            return Err(RomError::Truncated {
+                expected: HEADER_SIZE,
+                found: bytes.len(),
+            });
+        }
+        let flags6 = bytes[6];
+        let flags7 = bytes[7];
+        let format = if flags7 & 0x0C == 0x08 {
+            Format::Nes20
+        } else {
+            Format::INes
+        };
+        let mirroring = if flags6 & 0x08 != 0 {
+            Mirroring::FourScreen
+        } else if flags6 & 0x01 != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+        let battery = flags6 & 0x02 != 0;
+        let trainer = flags6 & 0x04 != 0;
+
+        match format {
+            Format::Nes20 => {
+                let console = match flags7 & 0x03 {
+                    0 => ConsoleType::Nes,
+                    1 => ConsoleType::VsSystem,
+                    2 => ConsoleType::Playchoice10,
+                    _ => ConsoleType::Extended(bytes[13] & 0x0F),
+                };
+                let prg_rom_size = rom_size(bytes[4], bytes[9] & 0x0F, PRG_ROM_UNIT)?;
+                let chr_rom_size = rom_size(bytes[5], bytes[9] >> 4, CHR_ROM_UNIT)?;
+                let timing = match bytes[12] & 0x03 {
+                    0 => Timing::Ntsc,
+                    1 => Timing::Pal,
+                    2 => Timing::MultiRegion,
+                    _ => Timing::Dendy,
+                };
+                Ok(Header {
+                    format,
+                    mapper: (flags6 >> 4) as u16
+                        | (flags7 & 0xF0) as u16
+                        | ((bytes[8] & 0x0F) as u16) << 8,
+                    submapper: bytes[8] >> 4,
+                    prg_rom_size,
+                    chr_rom_size,
+                    prg_ram_size: ram_size(bytes[10] & 0x0F),
+                    prg_nvram_size: ram_size(bytes[10] >> 4),
+                    chr_ram_size: ram_size(bytes[11] & 0x0F),
+                    chr_nvram_size: ram_size(bytes[11] >> 4),
+                    mirroring,
+                    battery,
+                    trainer,
+                    console,
+                    timing,
+                })
+            }
+            Format::INes => {
+                // Old dumping tools left their name in bytes 7-15, "DiskDude!" being the
+                // famous one. When the unused bytes aren't blank byte 7 can't be trusted.
+                let mapper_high = if bytes[12..16].iter().all(|&byte| byte == 0) {
+                    flags7 & 0xF0
+                } else {
+                    0
+                };
+                let mapper = ((flags6 >> 4) | mapper_high) as u16;
+                // iNES only has the two flags, PlayChoice-10 being the one with extra data
+                let console = if flags7 & 0x02 != 0 {
+                    ConsoleType::Playchoice10
+                } else if flags7 & 0x01 != 0 {
+                    ConsoleType::VsSystem
+                } else {
+                    ConsoleType::Nes
+                };
+                let chr_rom_size = bytes[5] as usize * CHR_ROM_UNIT;
+                // PRG RAM in 8K units. Hardly any dumps fill it in, so 0 means 8K for
+                // boards that need RAM for a battery or trainer or almost always have it.
+                let prg_ram_size = match bytes[8] {
+                    0 if battery || trainer || implies_prg_ram(mapper) => 0x2000,
+                    units => units as usize * 0x2000,
+                };
+                Ok(Header {
+                    format,
+                    mapper,
+                    submapper: 0,
+                    prg_rom_size: bytes[4] as usize * PRG_ROM_UNIT,
+                    chr_rom_size,
+                    prg_ram_size: if battery { 0 } else { prg_ram_size },
+                    prg_nvram_size: if battery { prg_ram_size } else { 0 },
+                    chr_ram_size: if chr_rom_size == 0 { CHR_ROM_UNIT } else { 0 },
+                    chr_nvram_size: 0,
+                    mirroring,
+                    battery,
+                    trainer,
+                    console,
+                    timing: if bytes[9] & 0x01 != 0 {
+                        Timing::Pal
+                    } else {
+                        Timing::Ntsc
+                    },
+                })
+            }
+        }
+    }
+}
+
+// Boards that come with PRG RAM on nearly every cartridge, for iNES headers that don't say
+fn implies_prg_ram(mapper: u16) -> bool {
+    matches!(mapper, 1 | 4 | 5 | 21 | 23 | 24 | 25 | 26 | 85)
+}
+
+// NES 2.0 ROM sizes. When the upper nibble is $F the low byte is instead EEEEEEMM, for a
+// size of 2^E * (MM * 2 + 1) bytes.
+fn rom_size(low: u8, high: u8, unit: usize) -> Result<usize, RomError> {
+    if high == 0x0F {
+        let exponent = (low >> 2) as u32;
+        let multiplier = (low & 0x03) as usize * 2 + 1;
+        1usize
+            .checked_shl(exponent)
+            .filter(|_| exponent < usize::BITS)
+            .and_then(|size| size.checked_mul(multiplier))
+            .ok_or(RomError::TooLarge)
+    } else {
+        Ok(((high as usize) << 8 | low as usize) * unit)
+    }
+}
+
+// NES 2.0 RAM sizes are 64 << shift bytes, with 0 meaning none
+fn ram_size(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
+}
+
+pub fn parse(bytes: &[u8]) -> Result<Cartridge, RomError> {
+    let header = Header::parse(bytes)?;
+    if header.prg_rom_size == 0 {
+        return Err(RomError::NoPrgRom);
+    }
+    let trainer_size = if header.trainer { TRAINER_SIZE } else { 0 };
+    let prg_start = HEADER_SIZE + trainer_size;
+    let chr_start = prg_start
+        .checked_add(header.prg_rom_size)
+        .ok_or(RomError::TooLarge)?;
+    let end = chr_start
+        .checked_add(header.chr_rom_size)
+        .ok_or(RomError::TooLarge)?;
+    if bytes.len() < end {
+        return Err(RomError::Truncated {
+            expected: end,
+            found: bytes.len(),
+        });
+    }
+    // Anything after CHR ROM is miscellaneous ROM data or padding, neither is used
+    Ok(Cartridge {
+        trainer: header
+            .trainer
+            .then(|| bytes[HEADER_SIZE..prg_start].to_vec()),
+        prg_rom: bytes[prg_start..chr_start].to_vec(),
+        chr_rom: bytes[chr_start..end].to_vec(),
+        header,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A ROM with the given header bytes 4-15 and as much blank data as they ask for
+    fn rom(header: [u8; 12]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(header);
+        let parsed = Header::parse(&bytes).unwrap();
+        let trainer = if parsed.trainer { TRAINER_SIZE } else { 0 };
+        bytes.resize(
+            HEADER_SIZE + trainer + parsed.prg_rom_size + parsed.chr_rom_size,
+            0,
+        );
+        bytes
+    }
+
+    fn header(bytes: [u8; 12]) -> Header {
+        Header::parse(&rom(bytes)).unwrap()
+    }
+
+    #[test]
+    fn not_ines() {
+        assert!(matches!(parse(b"NES"), Err(RomError::NotINes)));
+        assert!(matches!(parse(&[0; 16]), Err(RomError::NotINes)));
+    }
+
+    #[test]
+    fn truncated() {
+        assert!(matches!(
+            parse(b"NES\x1A\x01\x01"),
+            Err(RomError::Truncated {
+                expected: 16,
+                found: 6
+            })
+        ));
+        let mut bytes = rom([1, 1, 0x04, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert!(parse(&bytes).is_ok());
+        bytes.pop();
+        let expected = HEADER_SIZE + TRAINER_SIZE + 0x4000 + 0x2000;
+        assert!(matches!(
+            parse(&bytes),
+            Err(RomError::Truncated { expected: e, found }) if e == expected && found == expected - 1
+        ));
+    }
+
+    #[test]
+    fn no_prg_rom() {
+        let bytes = rom([0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert!(matches!(parse(&bytes), Err(RomError::NoPrgRom)));
+    }
+
+    #[test]
+    fn nes_20_sizes() {
+        // Mapper 260 submapper 3, the upper nibbles of the ROM sizes in byte 9
+        let parsed = Header::parse(
+            &[
+                &MAGIC[..],
+                &[2, 1, 0x40, 0x08, 0x31, 0x21, 0x07, 0x70, 0, 0, 0, 0],
+            ]
+            .concat(),
+        )
+        .unwrap();
+        assert_eq!(parsed.format, Format::Nes20);
+        assert_eq!((parsed.mapper, parsed.submapper), (260, 3));
+        assert_eq!(parsed.prg_rom_size, 0x102 * 0x4000);
+        assert_eq!(parsed.chr_rom_size, 0x201 * 0x2000);
+        assert_eq!((parsed.prg_ram_size, parsed.prg_nvram_size), (0x2000, 0));
+        assert_eq!((parsed.chr_ram_size, parsed.chr_nvram_size), (0, 0x2000));
+        // Exponent and multiplier, 2^14 * 3 and 2^13 * 1
+        let parsed = header([14 << 2 | 1, 13 << 2, 0, 0x08, 0, 0xFF, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(parsed.prg_rom_size, 0xC000);
+        assert_eq!(parsed.chr_rom_size, 0x2000);
+        let bytes = [&MAGIC[..], &[0xFF, 0, 0, 0x08, 0, 0x0F, 0, 0, 0, 0, 0, 0]].concat();
+        assert!(matches!(Header::parse(&bytes), Err(RomError::TooLarge)));
+    }
+
+    #[test]
+    fn ines_prg_ram() {
+        let sizes = |header: Header| (header.prg_ram_size, header.prg_nvram_size);
+        // NROM and UxROM don't have any unless something says so
+        assert_eq!(
+            sizes(header([1, 1, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, 0])),
+            (0, 0)
+        );
+        assert_eq!(
+            sizes(header([1, 0, 0x20, 0, 0, 0, 0, 0, 0, 0, 0, 0])),
+            (0, 0)
+        );
+        assert_eq!(
+            sizes(header([1, 1, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0])),
+            (0, 0x2000)
+        );
+        assert_eq!(
+            sizes(header([1, 1, 0x04, 0, 0, 0, 0, 0, 0, 0, 0, 0])),
+            (0x2000, 0)
+        );
+        assert_eq!(
+            sizes(header([1, 0, 0x20, 0, 2, 0, 0, 0, 0, 0, 0, 0])),
+            (0x4000, 0)
+        );
+        // MMC1 and MMC3 boards nearly always have it
+        assert_eq!(
+            sizes(header([2, 0, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0])),
+            (0x2000, 0)
+        );
+        assert_eq!(
+            sizes(header([2, 1, 0x40, 0, 0, 0, 0, 0, 0, 0, 0, 0])),
+            (0x2000, 0)
+        );
+    }
+
+    #[test]
+    fn console_type() {
+        // Byte 13 is only read in NES 2.0 headers
+        let parsed = header([1, 1, 0, 0x03, 0, 0, 0, 0, 0, 0x05, 0, 0]);
+        assert_eq!(parsed.console, ConsoleType::Playchoice10);
+        let parsed = header([1, 1, 0, 0x01, 0, 0, 0, 0, 0, 0x05, 0, 0]);
+        assert_eq!(parsed.console, ConsoleType::VsSystem);
+        let parsed = header([1, 1, 0, 0x0B, 0, 0, 0, 0, 0, 0x05, 0, 0]);
+        assert_eq!(parsed.console, ConsoleType::Extended(0x05));
+    }
+
+    #[test]
+    fn trainer_at_7000() {
+        let mut bytes = rom([1, 1, 0x04, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        bytes[HEADER_SIZE] = 0xA5;
+        bytes[HEADER_SIZE + TRAINER_SIZE - 1] = 0x5A;
+        bytes[HEADER_SIZE + TRAINER_SIZE] = 0x4C;
+        let cartridge = parse(&bytes).unwrap();
+        assert_eq!(cartridge.prg_rom[0], 0x4C);
+        let mut mapper = cartridge.into_mapper().unwrap();
+        assert_eq!(mapper.cpu_read(0x7000), Some(0xA5));
+        assert_eq!(mapper.cpu_read(0x71FF), Some(0x5A));
+        assert_eq!(mapper.cpu_read(0x6FFF), Some(0x00));
+    }
+}
@@ -0,0 +1,109 @@
+/* https://www.nesdev.org/wiki/AxROM
+* Writes to $8000-$FFFF pick a 32K PRG bank with bits 0-2, and which nametable is shown
+* everywhere with bit 4. CHR is 8K of RAM. Only AMROM has bus conflicts.
+*/
+
+use super::{super::Cartridge, bus_conflicts, Chr, Mapper, Mirroring};
+
+pub struct Axrom {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    bus_conflicts: bool,
+    register: u8,
+}
+
+impl Axrom {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Axrom {
+            chr: Chr::new(&cartridge),
+            bus_conflicts: bus_conflicts(cartridge.header.submapper, false),
+            register: 0,
+            prg_rom: cartridge.prg_rom,
+        }
+    }
+
+    fn prg_address(&self, address: u16) -> usize {
+        let bank = (self.register & 0x07) as usize;
+        (bank * 0x8000 + (address & 0x7FFF) as usize) % self.prg_rom.len()
+    }
+}
+
+impl Mapper for Axrom {
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_address(address)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        if address >= 0x8000 {
+            self.register = if self.bus_conflicts {
+                value & self.prg_rom[self.prg_address(address)]
+            } else {
+                value
+            };
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        self.chr.read(address as usize)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(address as usize, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.register & 0x10 == 0 {
+            Mirroring::SingleScreenLower
+        } else {
+            Mirroring::SingleScreenUpper
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::test_cartridge, Axrom, Mapper, Mirroring};
+
+    #[test]
+    fn prg_banks() {
+        let mut axrom = Axrom::new(test_cartridge(7, 0, 0x40000, 0));
+        assert_eq!(axrom.cpu_peek(0x8000), Some(0));
+        axrom.cpu_write(0x8000, 0x02);
+        assert_eq!(
+            [0x8000, 0xE000].map(|a| axrom.cpu_peek(a)),
+            [8, 11].map(Some)
+        );
+        // Banks past the end of a smaller ROM wrap around
+        let mut small = Axrom::new(test_cartridge(7, 0, 0x8000, 0));
+        small.cpu_write(0x8000, 0x07);
+        assert_eq!(small.cpu_peek(0xE000), Some(3));
+    }
+
+    #[test]
+    fn single_screen() {
+        let mut axrom = Axrom::new(test_cartridge(7, 0, 0x40000, 0));
+        assert_eq!(axrom.mirroring(), Mirroring::SingleScreenLower);
+        axrom.cpu_write(0x8000, 0x10);
+        assert_eq!(axrom.mirroring(), Mirroring::SingleScreenUpper);
+        axrom.cpu_write(0x8000, 0x00);
+        assert_eq!(axrom.mirroring(), Mirroring::SingleScreenLower);
+    }
+
+    // Off by default, only AMROM boards, NES 2.0 submapper 2, have them. $E000 holds $03.
+    #[test]
+    fn bus_conflicts() {
+        for (submapper, mirroring) in [
+            (0, Mirroring::SingleScreenUpper),
+            (1, Mirroring::SingleScreenUpper),
+            (2, Mirroring::SingleScreenLower),
+        ] {
+            let mut axrom = Axrom::new(test_cartridge(7, submapper, 0x40000, 0));
+            axrom.cpu_write(0xE000, 0x13);
+            assert_eq!(axrom.cpu_peek(0x8000), Some(12));
+            assert_eq!(axrom.mirroring(), mirroring);
+        }
+    }
+}
@@ -0,0 +1,88 @@
+/* https://www.nesdev.org/wiki/CNROM
+* 16K or 32K of PRG ROM like NROM. Any write to $8000-$FFFF picks the 8K CHR ROM bank.
+*/
+
+use super::{super::Cartridge, bus_conflicts, Chr, Mapper, Mirroring};
+
+pub struct Cnrom {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+    chr_bank: u8,
+}
+
+impl Cnrom {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Cnrom {
+            chr: Chr::new(&cartridge),
+            mirroring: cartridge.header.mirroring,
+            bus_conflicts: bus_conflicts(cartridge.header.submapper, true),
+            chr_bank: 0,
+            prg_rom: cartridge.prg_rom,
+        }
+    }
+
+    fn chr_address(&self, address: u16) -> usize {
+        self.chr_bank as usize * 0x2000 + address as usize
+    }
+}
+
+impl Mapper for Cnrom {
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x8000..=0xFFFF => Some(self.prg_rom[(address - 0x8000) as usize % self.prg_rom.len()]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        if address >= 0x8000 {
+            self.chr_bank = if self.bus_conflicts {
+                value & self.prg_rom[(address - 0x8000) as usize % self.prg_rom.len()]
+            } else {
+                value
+            };
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        self.chr.read(self.chr_address(address))
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        let address = self.chr_address(address);
+        self.chr.write(address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::test_cartridge, Cnrom, Mapper};
+
+    #[test]
+    fn chr_banks() {
+        let mut cnrom = Cnrom::new(test_cartridge(3, 0, 0x8000, 0x8000));
+        assert_eq!([0x0000, 0x1C00].map(|a| cnrom.ppu_peek(a)), [0, 7]);
+        // $E000 holds $03, so there's no conflict to worry about
+        cnrom.cpu_write(0xE000, 3);
+        assert_eq!([0x0000, 0x1C00].map(|a| cnrom.ppu_peek(a)), [24, 31]);
+        // 16K of PRG ROM is mirrored
+        let cnrom = Cnrom::new(test_cartridge(3, 0, 0x4000, 0x8000));
+        assert_eq!(cnrom.cpu_peek(0xC000), Some(0));
+    }
+
+    // On by default, off for NES 2.0 submapper 1. $C000 holds $02.
+    #[test]
+    fn bus_conflicts() {
+        for (submapper, bank) in [(0, 2), (1, 3), (2, 2)] {
+            let mut cnrom = Cnrom::new(test_cartridge(3, submapper, 0x8000, 0x8000));
+            cnrom.cpu_write(0xC000, 3);
+            assert_eq!(cnrom.ppu_peek(0x0000), bank * 8);
+        }
+    }
+}
@@ -0,0 +1,95 @@
+/* https://www.nesdev.org/wiki/GxROM
+* Writes to $8000-$FFFF pick a 32K PRG bank with bits 4-5 and an 8K CHR bank with
+* bits 0-1. Mirroring is soldered on the board.
+*/
+
+use super::{super::Cartridge, bus_conflicts, Chr, Mapper, Mirroring};
+
+pub struct Gxrom {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+    register: u8,
+}
+
+impl Gxrom {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Gxrom {
+            chr: Chr::new(&cartridge),
+            mirroring: cartridge.header.mirroring,
+            bus_conflicts: bus_conflicts(cartridge.header.submapper, true),
+            register: 0,
+            prg_rom: cartridge.prg_rom,
+        }
+    }
+
+    fn prg_address(&self, address: u16) -> usize {
+        let bank = ((self.register >> 4) & 0x03) as usize;
+        (bank * 0x8000 + (address & 0x7FFF) as usize) % self.prg_rom.len()
+    }
+
+    fn chr_address(&self, address: u16) -> usize {
+        (self.register & 0x03) as usize * 0x2000 + address as usize
+    }
+}
+
+impl Mapper for Gxrom {
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_address(address)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        if address >= 0x8000 {
+            self.register = if self.bus_conflicts {
+                value & self.prg_rom[self.prg_address(address)]
+            } else {
+                value
+            };
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        self.chr.read(self.chr_address(address))
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        let address = self.chr_address(address);
+        self.chr.write(address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::test_cartridge, Gxrom, Mapper};
+
+    #[test]
+    fn banks() {
+        let mut gxrom = Gxrom::new(test_cartridge(66, 1, 0x20000, 0x8000));
+        gxrom.cpu_write(0x8000, 0x21);
+        assert_eq!(
+            [0x8000, 0xE000].map(|a| gxrom.cpu_peek(a)),
+            [8, 11].map(Some)
+        );
+        assert_eq!([0x0000, 0x1C00].map(|a| gxrom.ppu_peek(a)), [8, 15]);
+    }
+
+    // On by default, off for NES 2.0 submapper 1. $E000 holds $03, which keeps the CHR
+    // bits and clears the PRG ones.
+    #[test]
+    fn bus_conflicts() {
+        for (submapper, prg_bank) in [(0, 0), (1, 2), (2, 0)] {
+            let mut gxrom = Gxrom::new(test_cartridge(66, submapper, 0x20000, 0x8000));
+            gxrom.cpu_write(0xE000, 0x21);
+            assert_eq!(gxrom.cpu_peek(0x8000), Some(prg_bank * 4));
+            assert_eq!(gxrom.ppu_peek(0x0000), 8);
+        }
+    }
+}
@@ -0,0 +1,396 @@
+/* https://www.nesdev.org/wiki/MMC1
+* Registers are loaded serially through $8000-$FFFF, one bit per write starting with the
+* lowest. The fifth write copies the shift register into the register picked by bits 13
+* and 14 of its address. A write with bit 7 set resets the shift register and switches
+* to PRG mode 3.
+*
+* $8000 Control   CPPMM  CHR mode, PRG mode, mirroring
+* $A000 CHR bank 0
+* $C000 CHR bank 1
+* $E000 PRG bank  RPPPP  PRG RAM disable, 16K PRG bank
+*
+* The chip ignores a write on the cycle straight after another one, so only the first of
+* the two writes a read-modify-write instruction makes counts.
+*
+* The larger SxROM boards reuse the upper CHR bank bits, which go unused with 8K of CHR:
+* SNROM  bit 4 disables PRG RAM
+* SOROM  bit 3 picks one of two 8K PRG RAM banks
+* SUROM  bit 4 picks which 256K half of the 512K PRG ROM is used
+* SXROM  bit 4 like SUROM, bits 2-3 pick one of four 8K PRG RAM banks
+* Which board it is comes from the PRG ROM and RAM sizes. The bits are taken from CHR bank
+* 0, the real chip uses whichever CHR bank the PPU last addressed.
+*/
+
+use super::{super::Cartridge, Chr, Mapper, Mirroring};
+
+// NES 2.0 submappers
+const MMC1A: u8 = 3;
+const SEROM: u8 = 5;
+
+pub struct Mmc1 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    submapper: u8,
+    shift: u8,
+    shift_count: u8,
+    control: u8,
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    prg_bank: u8,
+    // CPU cycle count, to spot writes on consecutive cycles
+    cycle: u64,
+    last_write: Option<u64>,
+}
+
+impl Mmc1 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        let header = &cartridge.header;
+        Mmc1 {
+            prg_ram: vec![0; header.prg_ram_size + header.prg_nvram_size],
+            chr: Chr::new(&cartridge),
+            submapper: header.submapper,
+            shift: 0,
+            shift_count: 0,
+            control: 0x0C,
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+            cycle: 0,
+            last_write: None,
+            prg_rom: cartridge.prg_rom,
+        }
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        let consecutive = self.last_write.is_some_and(|last| self.cycle <= last + 1);
+        self.last_write = Some(self.cycle);
+        if consecutive {
+            return;
+        }
+        if value & 0x80 != 0 {
+            self.shift = 0;
+            self.shift_count = 0;
+            self.control |= 0x0C;
+            return;
+        }
+        self.shift |= (value & 1) << self.shift_count;
+        self.shift_count += 1;
+        if self.shift_count == 5 {
+            match (address >> 13) & 0x03 {
+                0 => self.control = self.shift,
+                1 => self.chr_bank_0 = self.shift,
+                2 => self.chr_bank_1 = self.shift,
+                _ => self.prg_bank = self.shift,
+            }
+            self.shift = 0;
+            self.shift_count = 0;
+        }
+    }
+
+    fn prg_address(&self, address: u16) -> usize {
+        // SUROM and SXROM, the only boards with 512K
+        let outer = if self.prg_rom.len() > 0x40000 {
+            self.chr_bank_0 & 0x10
+        } else {
+            0
+        };
+        let bank = self.prg_bank & 0x0F;
+        let bank = if self.submapper == SEROM {
+            // SEROM and SHROM have 32K of PRG ROM and nothing to switch
+            (address >> 14 & 1) as u8
+        } else {
+            match (self.control >> 2) & 0x03 {
+                // Switch 32K at $8000, ignoring the low bit of the bank number
+                0 | 1 => outer | (bank & 0x0E) | (address >> 14 & 1) as u8,
+                // Fix the first bank at $8000 and switch 16K at $C000
+                2 if address < 0xC000 => outer,
+                2 => outer | bank,
+                // Fix the last bank at $C000 and switch 16K at $8000
+                _ if address < 0xC000 => outer | bank,
+                _ => outer | 0x0F,
+            }
+        };
+        (bank as usize * 0x4000 + (address & 0x3FFF) as usize) % self.prg_rom.len()
+    }
+
+    fn prg_ram_address(&self, address: u16) -> Option<usize> {
+        if self.prg_ram.is_empty() {
+            return None;
+        }
+        // MMC1A doesn't have the disable bit
+        if self.prg_bank & 0x10 != 0 && self.submapper != MMC1A {
+            return None;
+        }
+        // SNROM, 8K of CHR RAM and no PRG ROM outer bank to make room for
+        if self.chr.is_ram() && self.prg_rom.len() <= 0x40000 && self.chr_bank_0 & 0x10 != 0 {
+            return None;
+        }
+        let bank = match self.prg_ram.len() {
+            0x8000 => (self.chr_bank_0 >> 2) & 0x03,
+            0x4000 => (self.chr_bank_0 >> 3) & 0x01,
+            _ => 0,
+        };
+        Some((bank as usize * 0x2000 + (address & 0x1FFF) as usize) % self.prg_ram.len())
+    }
+
+    fn chr_address(&self, address: u16) -> usize {
+        let bank = if self.control & 0x10 == 0 {
+            // 8K mode, the low bit of the bank number is ignored
+            (self.chr_bank_0 & 0x1E) | (address >> 12 & 1) as u8
+        } else if address < 0x1000 {
+            self.chr_bank_0
+        } else {
+            self.chr_bank_1
+        };
+        bank as usize * 0x1000 + (address & 0x0FFF) as usize
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF => self
+                .prg_ram_address(address)
+                .map(|address| self.prg_ram[address]),
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_address(address)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7FFF => {
+                if let Some(address) = self.prg_ram_address(address) {
+                    self.prg_ram[address] = value;
+                }
+            }
+            0x8000..=0xFFFF => self.write_register(address, value),
+            _ => (),
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        self.chr.read(self.chr_address(address))
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        let address = self.chr_address(address);
+        self.chr.write(address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0x03 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn cpu_clock(&mut self) {
+        self.cycle += 1;
+    }
+
+    fn battery_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::test_cartridge, Mapper, Mirroring, Mmc1, MMC1A, SEROM};
+
+    fn mmc1(prg_size: usize, chr_size: usize) -> Mmc1 {
+        Mmc1::new(test_cartridge(1, 0, prg_size, chr_size))
+    }
+
+    // A single write, with a cycle in between so the next one isn't ignored
+    fn write(mmc1: &mut Mmc1, address: u16, value: u8) {
+        mmc1.cpu_write(address, value);
+        mmc1.cpu_clock();
+        mmc1.cpu_clock();
+    }
+
+    // Loads a register the way games do, five writes of one bit each
+    fn load(mmc1: &mut Mmc1, address: u16, value: u8) {
+        for bit in 0..5 {
+            write(mmc1, address, value >> bit & 1);
+        }
+    }
+
+    fn windows(mmc1: &Mmc1) -> [Option<u8>; 4] {
+        [0x8000, 0xA000, 0xC000, 0xE000].map(|address| mmc1.cpu_peek(address))
+    }
+
+    #[test]
+    fn serial_load() {
+        let mut mmc1 = mmc1(0x20000, 0x20000);
+        // Starts in PRG mode 3 with the last bank at $C000
+        assert_eq!(windows(&mmc1), [0, 1, 14, 15].map(Some));
+        for bit in 0..4 {
+            write(&mut mmc1, 0xE000, 0x03 >> bit & 1);
+        }
+        assert_eq!(windows(&mmc1), [0, 1, 14, 15].map(Some));
+        write(&mut mmc1, 0xE000, 0);
+        assert_eq!(windows(&mmc1), [6, 7, 14, 15].map(Some));
+        // Each register is picked by the address of the fifth write only
+        load(&mut mmc1, 0x8000, 0x10);
+        load(&mut mmc1, 0xA000, 5);
+        load(&mut mmc1, 0xDFFF, 7);
+        assert_eq!([0x0000, 0x1000].map(|a| mmc1.ppu_peek(a)), [20, 28]);
+    }
+
+    // Bit 7 throws away the bits loaded so far and switches to PRG mode 3
+    #[test]
+    fn reset() {
+        let mut mmc1 = mmc1(0x20000, 0x20000);
+        load(&mut mmc1, 0x8000, 0x00);
+        load(&mut mmc1, 0xE000, 3);
+        assert_eq!(windows(&mmc1), [4, 5, 6, 7].map(Some));
+        write(&mut mmc1, 0xE000, 1);
+        write(&mut mmc1, 0xE000, 1);
+        write(&mut mmc1, 0x8000, 0x80);
+        assert_eq!(windows(&mmc1), [6, 7, 14, 15].map(Some));
+        load(&mut mmc1, 0xE000, 2);
+        assert_eq!(windows(&mmc1), [4, 5, 14, 15].map(Some));
+    }
+
+    // The second write of a read-modify-write instruction lands on the next cycle and is
+    // ignored, which games rely on to reset the MMC1 with INC $FFFF
+    #[test]
+    fn consecutive_writes() {
+        let mut mmc1 = mmc1(0x20000, 0x20000);
+        mmc1.cpu_write(0xE000, 1);
+        mmc1.cpu_clock();
+        mmc1.cpu_write(0xE000, 0x80);
+        mmc1.cpu_clock();
+        mmc1.cpu_clock();
+        for bit in 1..5 {
+            write(&mut mmc1, 0xE000, 0x03 >> bit & 1);
+        }
+        assert_eq!(windows(&mmc1), [6, 7, 14, 15].map(Some));
+    }
+
+    #[test]
+    fn prg_modes() {
+        let mut mmc1 = mmc1(0x20000, 0x20000);
+        load(&mut mmc1, 0xE000, 3);
+        for (control, expected) in [
+            // 32K, the low bit of the bank is ignored
+            (0x00, [4, 5, 6, 7]),
+            (0x04, [4, 5, 6, 7]),
+            // The first bank fixed at $8000
+            (0x08, [0, 1, 6, 7]),
+            // The last bank fixed at $C000
+            (0x0C, [6, 7, 14, 15]),
+        ] {
+            load(&mut mmc1, 0x8000, control);
+            assert_eq!(windows(&mmc1), expected.map(Some));
+        }
+        // SEROM only has 32K, the registers don't matter
+        let mut serom = Mmc1::new(test_cartridge(1, SEROM, 0x8000, 0x8000));
+        load(&mut serom, 0xE000, 1);
+        assert_eq!(windows(&serom), [0, 1, 2, 3].map(Some));
+    }
+
+    #[test]
+    fn chr_modes() {
+        let mut mmc1 = mmc1(0x20000, 0x20000);
+        load(&mut mmc1, 0xA000, 5);
+        load(&mut mmc1, 0xC000, 9);
+        // 8K, the low bit of the bank is ignored and CHR bank 1 isn't used
+        load(&mut mmc1, 0x8000, 0x0C);
+        assert_eq!([0x0000, 0x1000].map(|a| mmc1.ppu_peek(a)), [16, 20]);
+        load(&mut mmc1, 0x8000, 0x1C);
+        assert_eq!([0x0000, 0x1000].map(|a| mmc1.ppu_peek(a)), [20, 36]);
+    }
+
+    #[test]
+    fn mirroring() {
+        let mut mmc1 = mmc1(0x20000, 0x20000);
+        for (control, expected) in [
+            (0x0C, Mirroring::SingleScreenLower),
+            (0x0D, Mirroring::SingleScreenUpper),
+            (0x0E, Mirroring::Vertical),
+            (0x0F, Mirroring::Horizontal),
+        ] {
+            load(&mut mmc1, 0x8000, control);
+            assert_eq!(mmc1.mirroring(), expected);
+        }
+    }
+
+    #[test]
+    fn prg_ram_disable() {
+        let mut mmc1 = mmc1(0x20000, 0x20000);
+        mmc1.cpu_write(0x6000, 0x42);
+        assert_eq!(mmc1.cpu_peek(0x6000), Some(0x42));
+        load(&mut mmc1, 0xE000, 0x10);
+        assert_eq!(mmc1.cpu_peek(0x6000), None);
+        // The MMC1A has no disable bit
+        let mut mmc1a = Mmc1::new(test_cartridge(1, MMC1A, 0x20000, 0x20000));
+        mmc1a.cpu_write(0x6000, 0x42);
+        load(&mut mmc1a, 0xE000, 0x10);
+        assert_eq!(mmc1a.cpu_peek(0x6000), Some(0x42));
+    }
+
+    // Told apart by having CHR RAM and no more than 256K of PRG ROM. With CHR ROM the same
+    // bit is part of the CHR bank and leaves the RAM alone.
+    #[test]
+    fn snrom() {
+        let mut snrom = mmc1(0x40000, 0);
+        snrom.cpu_write(0x6000, 0x42);
+        load(&mut snrom, 0xA000, 0x10);
+        assert_eq!(snrom.cpu_peek(0x6000), None);
+        load(&mut snrom, 0xA000, 0x00);
+        assert_eq!(snrom.cpu_peek(0x6000), Some(0x42));
+        let mut skrom = mmc1(0x40000, 0x20000);
+        skrom.cpu_write(0x6000, 0x42);
+        load(&mut skrom, 0xA000, 0x10);
+        assert_eq!(skrom.cpu_peek(0x6000), Some(0x42));
+    }
+
+    // 16K of PRG RAM, banked by bit 3
+    #[test]
+    fn sorom() {
+        let mut cartridge = test_cartridge(1, 0, 0x40000, 0);
+        cartridge.header.prg_ram_size = 0x4000;
+        let mut sorom = Mmc1::new(cartridge);
+        sorom.cpu_write(0x6000, 0x11);
+        load(&mut sorom, 0xA000, 0x08);
+        assert_eq!(sorom.cpu_peek(0x6000), Some(0));
+        sorom.cpu_write(0x6000, 0x22);
+        load(&mut sorom, 0xA000, 0x00);
+        assert_eq!(sorom.cpu_peek(0x6000), Some(0x11));
+    }
+
+    // 512K of PRG ROM, with bit 4 picking the 256K half even for the fixed bank
+    #[test]
+    fn surom() {
+        let mut surom = mmc1(0x80000, 0);
+        load(&mut surom, 0xE000, 2);
+        assert_eq!(windows(&surom), [4, 5, 30, 31].map(Some));
+        load(&mut surom, 0xA000, 0x10);
+        assert_eq!(windows(&surom), [36, 37, 62, 63].map(Some));
+        // It's not SNROM, so the same bit doesn't disable the RAM
+        surom.cpu_write(0x6000, 0x42);
+        assert_eq!(surom.cpu_peek(0x6000), Some(0x42));
+    }
+
+    // SUROM with 32K of PRG RAM, banked by bits 2-3
+    #[test]
+    fn sxrom() {
+        let mut cartridge = test_cartridge(1, 0, 0x80000, 0);
+        cartridge.header.prg_ram_size = 0x8000;
+        let mut sxrom = Mmc1::new(cartridge);
+        for bank in 0..4 {
+            load(&mut sxrom, 0xA000, 0x10 | bank << 2);
+            sxrom.cpu_write(0x6000, bank);
+        }
+        assert_eq!(windows(&sxrom)[2], Some(62));
+        for bank in 0..4 {
+            load(&mut sxrom, 0xA000, bank << 2);
+            assert_eq!(sxrom.cpu_peek(0x6000), Some(bank));
+        }
+    }
+}
@@ -0,0 +1,346 @@
+/* https://www.nesdev.org/wiki/MMC3
+* Even and odd addresses in each 8K range are two different registers.
+*
+* $8000 Bank select   CP...RRR  CHR A12 inversion, PRG mode, bank register to update
+* $8001 Bank data     the new value for the register picked by bank select
+* $A000 Mirroring     0 vertical, 1 horizontal
+* $A001 PRG RAM       ER......  enable, deny writes
+* $C000 IRQ latch     value the counter is reloaded with
+* $C001 IRQ reload    clears the counter so it's reloaded on the next clock
+* $E000 IRQ disable   also acknowledges a pending IRQ
+* $E001 IRQ enable
+*
+* The IRQ counter is clocked by rising edges of PPU A12, which happen once a scanline
+* when the background and sprites use different pattern tables. Rises that follow A12
+* being low for less than about 3 CPU cycles are filtered out, which hides the ones
+* between the sprite fetches of a scanline.
+* https://www.nesdev.org/wiki/MMC3#IRQ_Specifics
+*/
+
+use super::{super::Cartridge, Chr, Mapper, Mirroring};
+
+// CPU cycles A12 has to stay low before a rise clocks the counter
+const A12_FILTER: u64 = 3;
+
+// The revisions differ in what happens when the counter is reloaded with 0
+#[derive(Clone, Copy, PartialEq)]
+pub enum Revision {
+    // Only an IRQ when the counter is decremented to 0, or reloaded through $C001
+    Mmc3A,
+    // An IRQ every time the counter is clocked while it's 0, so a latch of 0 fires on
+    // every scanline
+    Mmc3BC,
+}
+
+pub struct Mmc3 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    four_screen: bool,
+    revision: Revision,
+    bank_select: u8,
+    // R0-R5 are CHR banks, R6 and R7 PRG banks
+    bank_registers: [u8; 8],
+    mirroring: Mirroring,
+    prg_ram_protect: u8,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+    // CPU cycle count and the state of A12, for the edge filter
+    cycle: u64,
+    a12: bool,
+    a12_fell: u64,
+}
+
+impl Mmc3 {
+    pub fn new(cartridge: Cartridge, revision: Revision) -> Self {
+        let header = &cartridge.header;
+        Mmc3 {
+            prg_ram: vec![0; header.prg_ram_size + header.prg_nvram_size],
+            chr: Chr::new(&cartridge),
+            four_screen: header.mirroring == Mirroring::FourScreen,
+            revision,
+            bank_select: 0,
+            bank_registers: [0; 8],
+            mirroring: Mirroring::Vertical,
+            // Games that never touch $A001 still expect their RAM to work
+            prg_ram_protect: 0x80,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            cycle: 0,
+            a12: false,
+            a12_fell: 0,
+            prg_rom: cartridge.prg_rom,
+        }
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        match address & 0xE001 {
+            0x8000 => self.bank_select = value,
+            0x8001 => self.bank_registers[(self.bank_select & 0x07) as usize] = value,
+            0xA000 => {
+                self.mirroring = if value & 0x01 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                }
+            }
+            0xA001 => self.prg_ram_protect = value,
+            0xC000 => self.irq_latch = value,
+            0xC001 => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            0xE000 => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            _ => self.irq_enabled = true,
+        }
+    }
+
+    fn prg_address(&self, address: u16) -> usize {
+        // The fixed banks count from the end, wrapping around on ROMs with fewer than two
+        let banks = (self.prg_rom.len() / 0x2000).max(1);
+        let last = banks - 1;
+        let swapped = self.bank_select & 0x40 != 0;
+        let bank = match ((address >> 13) & 0x03, swapped) {
+            (0, false) | (2, true) => self.bank_registers[6] as usize,
+            (0, true) | (2, false) => (last + banks - 1) % banks,
+            (1, _) => self.bank_registers[7] as usize,
+            _ => last,
+        };
+        (bank * 0x2000 + (address & 0x1FFF) as usize) % self.prg_rom.len()
+    }
+
+    fn chr_address(&self, address: u16) -> usize {
+        let address = address & 0x1FFF;
+        // Swaps the 2K banks and the 1K banks between the two pattern tables
+        let address = if self.bank_select & 0x80 != 0 {
+            address ^ 0x1000
+        } else {
+            address
+        };
+        let registers = &self.bank_registers;
+        let bank = match address >> 10 {
+            0 => registers[0] & 0xFE,
+            1 => registers[0] | 0x01,
+            2 => registers[1] & 0xFE,
+            3 => registers[1] | 0x01,
+            slot => registers[slot as usize - 2],
+        };
+        bank as usize * 0x400 + (address & 0x3FF) as usize
+    }
+
+    fn prg_ram_address(&self, address: u16) -> Option<usize> {
+        if self.prg_ram.is_empty() || self.prg_ram_protect & 0x80 == 0 {
+            return None;
+        }
+        Some((address - 0x6000) as usize % self.prg_ram.len())
+    }
+
+    fn watch_a12(&mut self, address: u16) {
+        let a12 = address & 0x1000 != 0;
+        if a12 && !self.a12 && self.cycle - self.a12_fell >= A12_FILTER {
+            self.clock_irq_counter();
+        } else if !a12 && self.a12 {
+            self.a12_fell = self.cycle;
+        }
+        self.a12 = a12;
+    }
+
+    fn clock_irq_counter(&mut self) {
+        let before = self.irq_counter;
+        let reload = self.irq_reload;
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+        let fire = match self.revision {
+            Revision::Mmc3A => self.irq_counter == 0 && (before > 0 || reload),
+            Revision::Mmc3BC => self.irq_counter == 0,
+        };
+        if fire && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF => self
+                .prg_ram_address(address)
+                .map(|address| self.prg_ram[address]),
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_address(address)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            // Bit 6 of the protect register denies writes
+            0x6000..=0x7FFF if self.prg_ram_protect & 0x40 == 0 => {
+                if let Some(address) = self.prg_ram_address(address) {
+                    self.prg_ram[address] = value;
+                }
+            }
+            0x8000..=0xFFFF => self.write_register(address, value),
+            _ => (),
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.watch_a12(address);
+        self.ppu_peek(address)
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        self.chr.read(self.chr_address(address))
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.watch_a12(address);
+        let address = self.chr_address(address);
+        self.chr.write(address, value);
+    }
+
+    fn ppu_address_bus(&mut self, address: u16) {
+        self.watch_a12(address);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.four_screen {
+            Mirroring::FourScreen
+        } else {
+            self.mirroring
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn cpu_clock(&mut self) {
+        self.cycle += 1;
+    }
+
+    fn battery_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::test_cartridge, Mapper, Mmc3, Revision, A12_FILTER};
+
+    fn mmc3(revision: Revision) -> Mmc3 {
+        Mmc3::new(test_cartridge(4, 0, 0x10000, 0x10000), revision)
+    }
+
+    // A12 stays low long enough and then rises, like once per scanline
+    fn scanline(mmc3: &mut Mmc3) {
+        mmc3.ppu_read(0x0000);
+        for _ in 0..A12_FILTER {
+            mmc3.cpu_clock();
+        }
+        mmc3.ppu_read(0x1000);
+    }
+
+    fn enable_irq(mmc3: &mut Mmc3, latch: u8) {
+        mmc3.cpu_write(0xC000, latch);
+        mmc3.cpu_write(0xC001, 0);
+        mmc3.cpu_write(0xE001, 0);
+    }
+
+    #[test]
+    fn prg_banks() {
+        let mut mmc3 = mmc3(Revision::Mmc3BC);
+        mmc3.cpu_write(0x8000, 0x06);
+        mmc3.cpu_write(0x8001, 3);
+        let windows = |mmc3: &Mmc3| [0x8000, 0xA000, 0xC000, 0xE000].map(|a| mmc3.cpu_peek(a));
+        assert_eq!(windows(&mmc3), [3, 0, 6, 7].map(Some));
+        mmc3.cpu_write(0x8000, 0x46);
+        assert_eq!(windows(&mmc3), [6, 0, 3, 7].map(Some));
+        // With a single 8K bank every window shows it
+        let mut small = Mmc3::new(test_cartridge(4, 0, 0x2000, 0x2000), Revision::Mmc3BC);
+        assert_eq!(windows(&small), [0, 0, 0, 0].map(Some));
+        small.cpu_write(0x8000, 0x40);
+        assert_eq!(windows(&small), [0, 0, 0, 0].map(Some));
+    }
+
+    #[test]
+    fn irq_reload() {
+        let mut mmc3 = mmc3(Revision::Mmc3BC);
+        enable_irq(&mut mmc3, 2);
+        // The first clock loads the latch, then it counts down to 0
+        scanline(&mut mmc3);
+        assert_eq!(mmc3.irq_counter, 2);
+        scanline(&mut mmc3);
+        assert!(!mmc3.irq());
+        scanline(&mut mmc3);
+        assert!(mmc3.irq());
+        mmc3.cpu_write(0xE000, 0);
+        assert!(!mmc3.irq());
+        // $C001 reloads on the next clock, whatever the counter was at
+        mmc3.cpu_write(0xE001, 0);
+        mmc3.cpu_write(0xC000, 5);
+        scanline(&mut mmc3);
+        assert_eq!(mmc3.irq_counter, 5);
+        scanline(&mut mmc3);
+        mmc3.cpu_write(0xC001, 0);
+        scanline(&mut mmc3);
+        assert_eq!(mmc3.irq_counter, 5);
+        assert!(!mmc3.irq());
+    }
+
+    // A latch of 0 fires on every scanline on the MMC3B and C, but on the MMC3A only after
+    // a reload through $C001
+    #[test]
+    fn zero_latch() {
+        for (revision, expected) in [
+            (Revision::Mmc3BC, [true, true]),
+            (Revision::Mmc3A, [true, false]),
+        ] {
+            let mut mmc3 = mmc3(revision);
+            enable_irq(&mut mmc3, 0);
+            let fired = [(); 2].map(|_| {
+                scanline(&mut mmc3);
+                let fired = mmc3.irq();
+                mmc3.cpu_write(0xE000, 0);
+                mmc3.cpu_write(0xE001, 0);
+                fired
+            });
+            assert_eq!(fired, expected);
+        }
+    }
+
+    // Rises after A12 was low only briefly, like between sprite fetches, don't count
+    #[test]
+    fn a12_filter() {
+        let mut mmc3 = mmc3(Revision::Mmc3BC);
+        enable_irq(&mut mmc3, 3);
+        scanline(&mut mmc3);
+        assert_eq!(mmc3.irq_counter, 3);
+        for _ in 0..4 {
+            mmc3.ppu_read(0x0000);
+            mmc3.cpu_clock();
+            mmc3.ppu_read(0x1000);
+        }
+        assert_eq!(mmc3.irq_counter, 3);
+        // Writes through $2006 put the address on the bus too
+        mmc3.ppu_address_bus(0x0000);
+        for _ in 0..A12_FILTER {
+            mmc3.cpu_clock();
+        }
+        mmc3.ppu_address_bus(0x1000);
+        assert_eq!(mmc3.irq_counter, 2);
+    }
+}
@@ -0,0 +1,655 @@
+/* https://www.nesdev.org/wiki/MMC5
+* $5100 PRG mode        0 32K, 1 16K+16K, 2 16K+8K+8K, 3 four 8K banks
+* $5101 CHR mode        0 8K, 1 4K, 2 2K, 3 1K banks
+* $5102 PRG RAM protect writes need $02 here and $01 in $5103
+* $5104 ExRAM mode      0 nametable, 1 extended attributes, 2 RAM, 3 read only RAM
+* $5105 Nametables      two bits per nametable, CIRAM page 0 or 1, ExRAM or fill mode
+* $5106 Fill tile       $5107 fill attribute
+* $5113 PRG RAM bank at $6000
+* $5114-$5117           PRG banks, bit 7 picks ROM over RAM. $5117 is always ROM.
+* $5120-$5127           CHR banks for sprites, and everything else in 8x8 sprite mode
+* $5128-$512B           CHR banks for the background in 8x16 sprite mode
+* $5130 Upper CHR bits  added to every CHR bank written after it
+* $5200 Vertical split  ES.TTTTT enable, right side, tile the split starts or ends on
+* $5201 Split scroll    $5202 split CHR bank
+* $5203 IRQ scanline    $5204 IRQ enable on writes, pending and in-frame on reads
+* $5205/$5206           Unsigned 8x8 multiplier, reads give the low and high byte
+* $5C00-$5FFF           1K of ExRAM
+*
+* The chip doesn't see the PPU's dots, it works out where the PPU is from what it fetches.
+* Three reads in a row from the same nametable address only happen at the end of a
+* scanline, so that's taken as the start of the next one. After that it counts fetches,
+* each scanline being 32 background tiles of four fetches, 8 sprites of four fetches and
+* then the first two tiles of the next scanline. When the PPU stops fetching for three CPU
+* cycles it's out of the frame.
+* https://www.nesdev.org/wiki/MMC5#Scanline_Detection_and_Scanline_IRQ
+*
+* Audio, the PCM channel and the MMC5A additions aren't emulated.
+*/
+
+use super::{
+    super::{Cartridge, Format},
+    Chr, Mapper, Mirroring,
+};
+
+// Fetches on a scanline, counted from the first background fetch
+const SPRITE_FETCHES: std::ops::Range<u8> = 128..160;
+const PREFETCHES: std::ops::Range<u8> = 160..168;
+
+// What the PPU is fetching, worked out from the number of fetches into the scanline
+#[derive(Clone, Copy, PartialEq)]
+enum Fetch {
+    // A background tile's nametable byte, with the tile's column and scanline
+    Name { column: u8, scanline: u8 },
+    Attribute,
+    Pattern,
+    Sprite,
+    // Outside the frame, the dummy nametable fetches and CPU accesses through $2007
+    Other,
+}
+
+pub struct Mmc5 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    exram: [u8; 0x400],
+    prg_mode: u8,
+    chr_mode: u8,
+    prg_ram_protect: [u8; 2],
+    exram_mode: u8,
+    nametables: u8,
+    fill_tile: u8,
+    fill_attribute: u8,
+    // $5113-$5117
+    prg_banks: [u8; 5],
+    // $5120-$512B with the upper bits from $5130 already added
+    chr_banks: [u16; 12],
+    chr_upper: u8,
+    // Whether $5128-$512B were written last, which decides the banks the CPU sees
+    // through $2007 outside the frame
+    chr_background_last: bool,
+    split_control: u8,
+    split_scroll: u8,
+    split_bank: u8,
+    irq_scanline: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    multiplicand: u8,
+    multiplier: u8,
+    // Snooped from PPUCTRL and PPUMASK
+    tall_sprites: bool,
+    // Scanline detection
+    in_frame: bool,
+    scanline: u8,
+    last_read: u16,
+    repeated_reads: u8,
+    fetches: u8,
+    idle_cycles: u8,
+    // The background tile being fetched, for extended attributes and the split
+    tile_attribute: u8,
+    tile_column: u8,
+    in_split: bool,
+    split_y: u8,
+}
+
+impl Mmc5 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        let header = &cartridge.header;
+        // iNES can't say how much RAM the board has, 64K covers all of them
+        let prg_ram_size = match header.format {
+            Format::INes => 0x10000,
+            Format::Nes20 => header.prg_ram_size + header.prg_nvram_size,
+        };
+        Mmc5 {
+            prg_ram: vec![0; prg_ram_size],
+            chr: Chr::new(&cartridge),
+            exram: [0; 0x400],
+            prg_mode: 3,
+            chr_mode: 0,
+            prg_ram_protect: [0; 2],
+            exram_mode: 0,
+            nametables: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            prg_banks: [0, 0, 0, 0, 0xFF],
+            chr_banks: [0; 12],
+            chr_upper: 0,
+            chr_background_last: false,
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            irq_scanline: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+            tall_sprites: false,
+            in_frame: false,
+            scanline: 0,
+            last_read: 0,
+            repeated_reads: 0,
+            fetches: 0,
+            idle_cycles: 0,
+            tile_attribute: 0,
+            tile_column: 0,
+            in_split: false,
+            split_y: 0,
+            prg_rom: cartridge.prg_rom,
+        }
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x5100 => self.prg_mode = value & 0x03,
+            0x5101 => self.chr_mode = value & 0x03,
+            0x5102 => self.prg_ram_protect[0] = value & 0x03,
+            0x5103 => self.prg_ram_protect[1] = value & 0x03,
+            0x5104 => self.exram_mode = value & 0x03,
+            0x5105 => self.nametables = value,
+            0x5106 => self.fill_tile = value,
+            0x5107 => self.fill_attribute = value & 0x03,
+            0x5113..=0x5117 => self.prg_banks[(address - 0x5113) as usize] = value,
+            0x5120..=0x512B => {
+                self.chr_banks[(address - 0x5120) as usize] =
+                    (self.chr_upper as u16) << 8 | value as u16;
+                self.chr_background_last = address >= 0x5128;
+            }
+            0x5130 => self.chr_upper = value & 0x03,
+            0x5200 => self.split_control = value,
+            0x5201 => self.split_scroll = value,
+            0x5202 => self.split_bank = value,
+            0x5203 => self.irq_scanline = value,
+            0x5204 => self.irq_enabled = value & 0x80 != 0,
+            0x5205 => self.multiplicand = value,
+            0x5206 => self.multiplier = value,
+            _ => (),
+        }
+    }
+
+    fn status(&self) -> u8 {
+        (self.irq_pending as u8) << 7 | (self.in_frame as u8) << 6
+    }
+
+    fn product(&self) -> u16 {
+        self.multiplicand as u16 * self.multiplier as u16
+    }
+
+    // Whether an 8K slot of $8000-$FFFF has ROM in it, and which 8K bank
+    fn prg_bank(&self, address: u16) -> (bool, u8) {
+        let slot = ((address >> 13) & 0x03) as u8;
+        let [_, bank_8000, bank_a000, bank_c000, bank_e000] = self.prg_banks;
+        let rom = |bank: u8| bank & 0x80 != 0;
+        match (self.prg_mode, slot) {
+            (0, _) => (true, (bank_e000 & 0x7C) | slot),
+            (1 | 2, 0 | 1) => (rom(bank_a000), (bank_a000 & 0x7E) | slot),
+            (1, _) => (true, (bank_e000 & 0x7E) | (slot & 0x01)),
+            (2, 2) => (rom(bank_c000), bank_c000),
+            (3, 0) => (rom(bank_8000), bank_8000),
+            (3, 1) => (rom(bank_a000), bank_a000),
+            (3, 2) => (rom(bank_c000), bank_c000),
+            _ => (true, bank_e000),
+        }
+    }
+
+    // Where a CPU address lands, ROM or RAM
+    fn prg_address(&self, address: u16) -> Prg {
+        let offset = (address & 0x1FFF) as usize;
+        let (rom, bank) = match address {
+            0x6000..=0x7FFF => (false, self.prg_banks[0]),
+            _ => self.prg_bank(address),
+        };
+        if rom {
+            Prg::Rom(((bank & 0x7F) as usize * 0x2000 + offset) % self.prg_rom.len())
+        } else if self.prg_ram.is_empty() {
+            Prg::None
+        } else {
+            Prg::Ram(((bank & 0x0F) as usize * 0x2000 + offset) % self.prg_ram.len())
+        }
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [0x02, 0x01]
+    }
+
+    // Banks $5120-$5127 if sprites is set, otherwise $5128-$512B which only cover 4K and
+    // repeat in both pattern tables
+    fn chr_address(&self, address: u16, sprites: bool) -> usize {
+        let address = address & 0x1FFF;
+        let (banks, slot) = if sprites {
+            (&self.chr_banks[..8], address >> 10)
+        } else {
+            (&self.chr_banks[8..], (address >> 10) & 0x03)
+        };
+        let (register, size) = match self.chr_mode {
+            0 => (7, 0x2000),
+            1 => (slot | 0x03, 0x1000),
+            2 => (slot | 0x01, 0x800),
+            _ => (slot, 0x400),
+        };
+        let bank = banks[register as usize % banks.len()] as usize;
+        bank * size + (address as usize & (size - 1))
+    }
+
+    // Counts PPU fetches to work out what's being fetched, see the top of the file
+    fn fetch(&mut self, address: u16) -> Fetch {
+        self.idle_cycles = 0;
+        if (0x2000..=0x2FFF).contains(&address) && address == self.last_read {
+            self.repeated_reads += 1;
+        } else {
+            self.repeated_reads = 0;
+        }
+        self.last_read = address;
+        if self.repeated_reads == 2 {
+            self.repeated_reads = 0;
+            self.fetches = 0;
+            self.start_scanline();
+        }
+        let fetch = self.fetches;
+        self.fetches = self.fetches.saturating_add(1);
+        if !self.in_frame {
+            return Fetch::Other;
+        }
+        let (tile, scanline) = if fetch < SPRITE_FETCHES.start {
+            (fetch / 4 + 2, self.scanline)
+        } else if SPRITE_FETCHES.contains(&fetch) {
+            return Fetch::Sprite;
+        } else if PREFETCHES.contains(&fetch) {
+            (
+                (fetch - PREFETCHES.start) / 4,
+                self.scanline.wrapping_add(1),
+            )
+        } else {
+            return Fetch::Other;
+        };
+        match fetch % 4 {
+            0 => Fetch::Name {
+                column: tile,
+                scanline,
+            },
+            1 => Fetch::Attribute,
+            _ => Fetch::Pattern,
+        }
+    }
+
+    fn start_scanline(&mut self) {
+        if self.in_frame {
+            self.scanline = self.scanline.wrapping_add(1);
+            if self.scanline == self.irq_scanline {
+                self.irq_pending = true;
+            }
+        } else {
+            self.in_frame = true;
+            self.scanline = 0;
+            self.irq_pending = false;
+        }
+    }
+
+    fn split_covers(&self, column: u8) -> bool {
+        // The split only works while ExRAM is a nametable or extended attributes
+        if self.split_control & 0x80 == 0 || self.exram_mode > 1 {
+            return false;
+        }
+        let tiles = self.split_control & 0x1F;
+        if self.split_control & 0x40 == 0 {
+            column < tiles
+        } else {
+            column >= tiles
+        }
+    }
+
+    // The console's nametables, ExRAM or fill mode depending on $5105
+    fn nametable(&self, address: u16) -> Option<u8> {
+        let offset = (address & 0x3FF) as usize;
+        match (self.nametables >> ((address >> 9) & 0x06)) & 0x03 {
+            0 | 1 => None,
+            2 if self.exram_mode <= 1 => Some(self.exram[offset]),
+            2 => Some(0),
+            _ if offset < 0x3C0 => Some(self.fill_tile),
+            _ => Some(self.fill_attribute * 0x55),
+        }
+    }
+
+    // The split's own nametable lives in ExRAM, scrolled by $5201
+    fn split_nametable(&self, column: u8, attribute: bool) -> u8 {
+        let column = (column & 0x1F) as usize;
+        let row = (self.split_y / 8) as usize;
+        if attribute {
+            let value = self.exram[0x3C0 + row / 4 * 8 + column / 4];
+            let shift = (row & 0x02) << 1 | (column & 0x02);
+            (value >> shift & 0x03) * 0x55
+        } else {
+            self.exram[row * 32 + column]
+        }
+    }
+}
+
+// Where a CPU access to $6000-$FFFF ends up
+enum Prg {
+    Rom(usize),
+    Ram(usize),
+    None,
+}
+
+impl Mapper for Mmc5 {
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x5204 => {
+                let status = self.status();
+                self.irq_pending = false;
+                Some(status)
+            }
+            // Reading the NMI vector means vblank has started
+            0xFFFA | 0xFFFB => {
+                self.in_frame = false;
+                self.cpu_peek(address)
+            }
+            _ => self.cpu_peek(address),
+        }
+    }
+
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x5204 => Some(self.status()),
+            0x5205 => Some(self.product() as u8),
+            0x5206 => Some((self.product() >> 8) as u8),
+            // ExRAM can't be read by the CPU while the PPU is using it
+            0x5C00..=0x5FFF if self.exram_mode >= 2 => Some(self.exram[address as usize - 0x5C00]),
+            0x6000..=0xFFFF => match self.prg_address(address) {
+                Prg::Rom(address) => Some(self.prg_rom[address]),
+                Prg::Ram(address) => Some(self.prg_ram[address]),
+                Prg::None => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x5000..=0x5BFF => self.write_register(address, value),
+            0x5C00..=0x5FFF => {
+                let offset = address as usize - 0x5C00;
+                match self.exram_mode {
+                    // Only writable while rendering, zeros get written otherwise
+                    0 | 1 => self.exram[offset] = if self.in_frame { value } else { 0 },
+                    2 => self.exram[offset] = value,
+                    _ => (),
+                }
+            }
+            0x6000..=0xFFFF if self.prg_ram_writable() => {
+                if let Prg::Ram(address) = self.prg_address(address) {
+                    self.prg_ram[address] = value;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        let fetch = self.fetch(address);
+        let address = match fetch {
+            Fetch::Pattern if self.in_split => {
+                let offset = (address & 0x0FF8) | (self.split_y & 0x07) as u16;
+                self.split_bank as usize * 0x1000 + offset as usize
+            }
+            // The 4K bank comes from the tile's ExRAM byte
+            Fetch::Pattern if self.exram_mode == 1 => {
+                let bank = (self.chr_upper as usize) << 6 | (self.tile_attribute & 0x3F) as usize;
+                bank * 0x1000 + (address & 0x0FFF) as usize
+            }
+            Fetch::Pattern => self.chr_address(address, !self.tall_sprites),
+            Fetch::Sprite => self.chr_address(address, true),
+            _ => return self.ppu_peek(address),
+        };
+        self.chr.read(address)
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        self.chr
+            .read(self.chr_address(address, !self.chr_background_last))
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        let address = self.chr_address(address, !self.chr_background_last);
+        self.chr.write(address, value);
+    }
+
+    fn nametable_read(&mut self, address: u16) -> Option<u8> {
+        match self.fetch(address) {
+            Fetch::Name { column, scanline } => {
+                let offset = (address & 0x3FF) as usize;
+                self.tile_attribute = self.exram[offset];
+                self.tile_column = column;
+                self.in_split = self.split_covers(column);
+                if self.in_split {
+                    let y = self.split_scroll as u16 + scanline as u16;
+                    self.split_y = if y >= 240 { y - 240 } else { y } as u8;
+                    return Some(self.split_nametable(column, false));
+                }
+            }
+            Fetch::Attribute if self.in_split => {
+                return Some(self.split_nametable(self.tile_column, true));
+            }
+            // The palette comes from the tile's ExRAM byte
+            Fetch::Attribute if self.exram_mode == 1 => {
+                return Some((self.tile_attribute >> 6) * 0x55);
+            }
+            _ => self.in_split = false,
+        }
+        self.nametable(address)
+    }
+
+    fn nametable_write(&mut self, address: u16, value: u8) -> bool {
+        match (self.nametables >> ((address >> 9) & 0x06)) & 0x03 {
+            0 | 1 => false,
+            2 => {
+                if self.exram_mode <= 1 {
+                    self.exram[(address & 0x3FF) as usize] = value;
+                }
+                true
+            }
+            _ => true,
+        }
+    }
+
+    fn ppu_register_write(&mut self, address: u16, value: u8) {
+        match address & 0x07 {
+            0 => self.tall_sprites = value & 0x20 != 0,
+            // Turning rendering off ends the frame
+            1 if value & 0x18 == 0 => self.in_frame = false,
+            _ => (),
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        // ExRAM and fill mode nametables are answered by nametable_read, the page given for
+        // them here is never used
+        let page = |quadrant: u8| (self.nametables >> (quadrant * 2)) & 0x01;
+        Mirroring::Custom([page(0), page(1), page(2), page(3)])
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending && self.irq_enabled
+    }
+
+    fn cpu_clock(&mut self) {
+        if self.idle_cycles < 3 {
+            self.idle_cycles += 1;
+            if self.idle_cycles == 3 {
+                self.in_frame = false;
+            }
+        }
+    }
+
+    fn battery_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::test_cartridge, Mapper, Mmc5};
+
+    fn mmc5() -> Mmc5 {
+        let mut mmc5 = Mmc5::new(test_cartridge(5, 0, 0x20000, 0x20000));
+        // Rendering on, 8x8 sprites
+        mmc5.ppu_register_write(0x2000, 0x00);
+        mmc5.ppu_register_write(0x2001, 0x18);
+        mmc5
+    }
+
+    // Fills ExRAM through the CPU, which always works in mode 2, then switches to `mode`
+    fn fill_exram(mmc5: &mut Mmc5, bytes: &[(u16, u8)], mode: u8) {
+        mmc5.cpu_write(0x5104, 2);
+        for &(offset, value) in bytes {
+            mmc5.cpu_write(0x5C00 + offset, value);
+        }
+        mmc5.cpu_write(0x5104, mode);
+    }
+
+    // Makes the fetches the PPU does for one scanline. Returns the nametable byte,
+    // attribute byte and low pattern byte of the tiles fetched between dots 1 and 256,
+    // which are tiles 2 to 33 since the first two were fetched on the line before.
+    fn scanline(mmc5: &mut Mmc5) -> Vec<(Option<u8>, Option<u8>, u8)> {
+        let mut tiles = Vec::new();
+        for column in 2..34 {
+            let name = mmc5.nametable_read(0x2000 | (column & 0x1F));
+            let attribute = mmc5.nametable_read(0x23C0 | (column & 0x1F) >> 2);
+            let pattern = mmc5.ppu_read(0x0000);
+            mmc5.ppu_read(0x0008);
+            tiles.push((name, attribute, pattern));
+        }
+        for _ in 0..8 {
+            mmc5.nametable_read(0x2000);
+            mmc5.nametable_read(0x2000);
+            mmc5.ppu_read(0x1000);
+            mmc5.ppu_read(0x1008);
+        }
+        for column in 0..2 {
+            mmc5.nametable_read(0x2000 | column);
+            mmc5.nametable_read(0x23C0);
+            mmc5.ppu_read(0x0000);
+            mmc5.ppu_read(0x0008);
+        }
+        // The two dummy fetches, the first fetch of the next line makes it three in a row
+        mmc5.nametable_read(0x2002);
+        mmc5.nametable_read(0x2002);
+        tiles
+    }
+
+    #[test]
+    fn multiplier() {
+        let mut mmc5 = mmc5();
+        assert_eq!(
+            (mmc5.cpu_peek(0x5205), mmc5.cpu_peek(0x5206)),
+            (Some(0x01), Some(0xFE))
+        );
+        mmc5.cpu_write(0x5205, 200);
+        mmc5.cpu_write(0x5206, 100);
+        // 20000 is $4E20
+        assert_eq!(
+            (mmc5.cpu_read(0x5205), mmc5.cpu_read(0x5206)),
+            (Some(0x20), Some(0x4E))
+        );
+        mmc5.cpu_write(0x5206, 0);
+        assert_eq!(
+            (mmc5.cpu_read(0x5205), mmc5.cpu_read(0x5206)),
+            (Some(0), Some(0))
+        );
+    }
+
+    #[test]
+    fn exram_modes() {
+        let mut mmc5 = mmc5();
+        // Modes 2 and 3 make it CPU RAM, read only in 3
+        fill_exram(&mut mmc5, &[(0x000, 0x12)], 3);
+        mmc5.cpu_write(0x5C00, 0x34);
+        assert_eq!(mmc5.cpu_peek(0x5C00), Some(0x12));
+        // In modes 0 and 1 the CPU can't read it, and writes outside the frame store 0
+        mmc5.cpu_write(0x5104, 0);
+        assert_eq!(mmc5.cpu_peek(0x5C00), None);
+        mmc5.cpu_write(0x5C00, 0x34);
+        mmc5.cpu_write(0x5104, 2);
+        assert_eq!(mmc5.cpu_peek(0x5C00), Some(0x00));
+        // As a nametable, which reads as 0 once the CPU has it
+        fill_exram(&mut mmc5, &[(0x001, 0x56)], 0);
+        mmc5.cpu_write(0x5105, 0b00_00_00_10);
+        assert_eq!(mmc5.nametable_read(0x2001), Some(0x56));
+        assert_eq!(mmc5.nametable_read(0x2401), None);
+        mmc5.cpu_write(0x5104, 2);
+        assert_eq!(mmc5.nametable_read(0x2001), Some(0x00));
+        // Fill mode
+        mmc5.cpu_write(0x5105, 0b00_00_00_11);
+        mmc5.cpu_write(0x5106, 0x42);
+        mmc5.cpu_write(0x5107, 0x02);
+        assert_eq!(mmc5.nametable_read(0x2001), Some(0x42));
+        assert_eq!(mmc5.nametable_read(0x23C1), Some(0xAA));
+    }
+
+    // Mode 1 gives every background tile its own 4K CHR bank and palette from ExRAM
+    #[test]
+    fn extended_attributes() {
+        let mut mmc5 = mmc5();
+        fill_exram(&mut mmc5, &[(0x002, 0b10_000101), (0x003, 0b01_000001)], 1);
+        scanline(&mut mmc5);
+        let tiles = scanline(&mut mmc5);
+        // 4K bank 5 starts with 1K bank 20
+        assert_eq!(tiles[0], (None, Some(0xAA), 20));
+        assert_eq!(tiles[1], (None, Some(0x55), 4));
+        // The sprites still use the normal banks
+        mmc5.cpu_write(0x5101, 3);
+        mmc5.cpu_write(0x5124, 9);
+        assert_eq!(mmc5.ppu_peek(0x1000), 9);
+    }
+
+    #[test]
+    fn vertical_split() {
+        let mut mmc5 = mmc5();
+        let mut exram: Vec<(u16, u8)> = (0..0x3C0).map(|offset| (offset, offset as u8)).collect();
+        // The attribute for row 1, columns 2 and 3
+        exram.push((0x3C0, 0b0000_0100));
+        fill_exram(&mut mmc5, &exram, 0);
+        // The 8 leftmost tiles, scrolled down one row, from 4K bank 3
+        mmc5.cpu_write(0x5200, 0x88);
+        mmc5.cpu_write(0x5201, 8);
+        mmc5.cpu_write(0x5202, 3);
+        scanline(&mut mmc5);
+        let tiles = scanline(&mut mmc5);
+        assert_eq!(tiles[0], (Some(32 + 2), Some(0x55), 12));
+        assert_eq!(tiles[5], (Some(32 + 7), Some(0x00), 12));
+        // The rest come from the console's nametables and the normal CHR banks
+        assert_eq!(tiles[6], (None, None, 0));
+        // On the right side instead
+        mmc5.cpu_write(0x5200, 0xC8);
+        let tiles = scanline(&mut mmc5);
+        assert_eq!(tiles[0], (None, None, 0));
+        assert_eq!(tiles[6].2, 12);
+        // Only in ExRAM modes 0 and 1
+        mmc5.cpu_write(0x5104, 2);
+        let tiles = scanline(&mut mmc5);
+        assert_eq!(tiles[6], (None, None, 0));
+    }
+
+    // Debuggers peeking don't acknowledge the IRQ, end the frame or count as fetches
+    #[test]
+    fn peek_has_no_side_effects() {
+        let mut mmc5 = mmc5();
+        mmc5.cpu_write(0x5203, 1);
+        mmc5.cpu_write(0x5204, 0x80);
+        for _ in 0..3 {
+            scanline(&mut mmc5);
+        }
+        assert!(mmc5.irq());
+        let fetches = mmc5.fetches;
+        assert_eq!(mmc5.cpu_peek(0x5204), Some(0xC0));
+        assert_eq!(mmc5.cpu_peek(0x5204), Some(0xC0));
+        assert_eq!(mmc5.cpu_peek(0xFFFA), mmc5.cpu_peek(0xFFFA));
+        mmc5.ppu_peek(0x0000);
+        assert_eq!(mmc5.fetches, fetches);
+        assert!(mmc5.irq());
+        // Reads do all of that
+        assert_eq!(mmc5.cpu_read(0x5204), Some(0xC0));
+        assert!(!mmc5.irq());
+        mmc5.cpu_read(0xFFFA);
+        assert_eq!(mmc5.cpu_peek(0x5204), Some(0x00));
+    }
+}
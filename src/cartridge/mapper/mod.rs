@@ -0,0 +1,217 @@
+/* https://www.nesdev.org/wiki/Mapper
+* The cartridge sees everything the CPU does from $4020 to $FFFF and everything the PPU
+* does in the pattern tables at $0000-$1FFF. Boards with bank switching decide what's
+* visible where, and some of them also control nametable mirroring and raise IRQs.
+*/
+
+mod axrom;
+mod cnrom;
+mod gxrom;
+mod mmc1;
+mod mmc3;
+mod mmc5;
+mod nrom;
+mod uxrom;
+mod vrc;
+mod vrc4;
+mod vrc6;
+mod vrc7;
+
+use std::io;
+
+use super::{Cartridge, Mirroring, RomError};
+
+pub use axrom::Axrom;
+pub use cnrom::Cnrom;
+pub use gxrom::Gxrom;
+pub use mmc1::Mmc1;
+pub use mmc3::{Mmc3, Revision};
+pub use mmc5::Mmc5;
+pub use nrom::Nrom;
+pub use uxrom::Uxrom;
+pub use vrc4::Vrc4;
+pub use vrc6::Vrc6;
+pub use vrc7::Vrc7;
+
+pub trait Mapper {
+    // $4020-$FFFF. None where the cartridge doesn't drive the bus, which reads as open bus.
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        self.cpu_peek(address)
+    }
+
+    // cpu_read without side effects, for debuggers
+    fn cpu_peek(&self, address: u16) -> Option<u8>;
+
+    fn cpu_write(&mut self, address: u16, value: u8);
+
+    // $0000-$1FFF
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.ppu_peek(address)
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8;
+
+    fn ppu_write(&mut self, address: u16, value: u8);
+
+    // Addresses the PPU puts on its bus without reading or writing, like $2006 writes.
+    // Boards that watch the address lines need them.
+    fn ppu_address_bus(&mut self, _address: u16) {}
+
+    // $2000-$2FFF. None leaves it to the console's nametable RAM, laid out by mirroring.
+    // The PPU goes through here for every nametable and attribute fetch, even the ones the
+    // board doesn't answer, since some boards count them.
+    fn nametable_read(&mut self, address: u16) -> Option<u8> {
+        self.ppu_address_bus(address);
+        None
+    }
+
+    // Whether the board took the write, otherwise it goes to the console's nametable RAM
+    fn nametable_write(&mut self, address: u16, _value: u8) -> bool {
+        self.ppu_address_bus(address);
+        false
+    }
+
+    // CPU writes to the PPU registers at $2000-$3FFF, for boards that keep an eye on how
+    // the PPU is set up
+    fn ppu_register_write(&mut self, _address: u16, _value: u8) {}
+
+    fn mirroring(&self) -> Mirroring;
+
+    // Whether the cartridge is pulling the CPU's IRQ line
+    fn irq(&self) -> bool {
+        false
+    }
+
+    // Called once every CPU cycle, for boards that count cycles
+    fn cpu_clock(&mut self) {}
+
+    // The RAM a battery would keep powered, or the EEPROM, if the board has a place for
+    // it. Whether it's actually kept between sessions is up to the header's battery flag.
+    fn battery_ram(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+
+    // Writes whatever the board keeps between sessions out to disk, see Battery. Meant for
+    // a frontend to call between frames, where errors can be shown to the player.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Picks the board for the mapper number in the header
+pub fn create(cartridge: Cartridge) -> Result<Box<dyn Mapper>, RomError> {
+    match cartridge.header.mapper {
+        0 => Ok(Box::new(Nrom::new(cartridge))),
+        1 => Ok(Box::new(Mmc1::new(cartridge))),
+        2 => Ok(Box::new(Uxrom::new(cartridge))),
+        3 => Ok(Box::new(Cnrom::new(cartridge))),
+        4 => {
+            let revision = match cartridge.header.submapper {
+                4 => Revision::Mmc3A,
+                _ => Revision::Mmc3BC,
+            };
+            Ok(Box::new(Mmc3::new(cartridge, revision)))
+        }
+        5 => Ok(Box::new(Mmc5::new(cartridge))),
+        7 => Ok(Box::new(Axrom::new(cartridge))),
+        21 | 22 | 23 | 25 => Ok(Box::new(Vrc4::new(cartridge))),
+        24 | 26 => Ok(Box::new(Vrc6::new(cartridge))),
+        66 => Ok(Box::new(Gxrom::new(cartridge))),
+        85 => Ok(Box::new(Vrc7::new(cartridge))),
+        mapper => Err(RomError::UnsupportedMapper(mapper)),
+    }
+}
+
+// Boards built from discrete logic chips let the ROM drive the data bus while the CPU is
+// writing to it, so the value that gets latched is the two ANDed together. NES 2.0
+// submapper 1 says the board has no conflicts and 2 that it has, otherwise it's up to
+// what the board usually does.
+// https://www.nesdev.org/wiki/Bus_conflict
+pub fn bus_conflicts(submapper: u8, default: bool) -> bool {
+    match submapper {
+        1 => false,
+        2 => true,
+        _ => default,
+    }
+}
+
+// CHR ROM if the cartridge has some, otherwise CHR RAM that can be written to
+pub struct Chr {
+    memory: Vec<u8>,
+    writable: bool,
+}
+
+impl Chr {
+    pub fn new(cartridge: &Cartridge) -> Self {
+        if cartridge.chr_rom.is_empty() {
+            let size = cartridge.header.chr_ram_size + cartridge.header.chr_nvram_size;
+            Chr {
+                memory: vec![0; size.max(0x2000)],
+                writable: true,
+            }
+        } else {
+            Chr {
+                memory: cartridge.chr_rom.clone(),
+                writable: false,
+            }
+        }
+    }
+
+    pub fn is_ram(&self) -> bool {
+        self.writable
+    }
+
+    // Banks wrap around when the board has less memory than the registers can select
+    pub fn read(&self, address: usize) -> u8 {
+        self.memory[address % self.memory.len()]
+    }
+
+    pub fn write(&mut self, address: usize, value: u8) {
+        if self.writable {
+            let length = self.memory.len();
+            self.memory[address % length] = value;
+        }
+    }
+}
+
+// A cartridge with an NES 2.0 header, 8K of PRG RAM, and every 8K PRG bank and 1K CHR bank
+// filled with its own number so tests can tell which one is mapped in. The sizes have to
+// be powers of two, no CHR ROM means 8K of CHR RAM.
+#[cfg(test)]
+fn test_cartridge(mapper: u16, submapper: u8, prg_size: usize, chr_size: usize) -> Cartridge {
+    // Exponent notation, so sizes under 16K work too
+    let size = |size: usize| (size.trailing_zeros() as u8) << 2;
+    let mut bytes = vec![
+        b'N',
+        b'E',
+        b'S',
+        0x1A,
+        size(prg_size),
+        if chr_size == 0 { 0 } else { size(chr_size) },
+        (mapper as u8) << 4,
+        (mapper as u8 & 0xF0) | 0x08,
+        submapper << 4 | (mapper >> 8) as u8,
+        if chr_size == 0 { 0x0F } else { 0xFF },
+        0x07,
+        if chr_size == 0 { 0x07 } else { 0 },
+        0,
+        0,
+        0,
+        0,
+    ];
+    bytes.extend((0..prg_size).map(|i| (i / 0x2000) as u8));
+    bytes.extend((0..chr_size).map(|i| (i / 0x400) as u8));
+    Cartridge::from_bytes(&bytes).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{create, test_cartridge, RomError};
+
+    #[test]
+    fn unsupported_mapper() {
+        let created = create(test_cartridge(0x123, 0, 0x8000, 0x2000));
+        assert!(matches!(created, Err(RomError::UnsupportedMapper(0x123))));
+        assert!(create(test_cartridge(0, 0, 0x8000, 0x2000)).is_ok());
+    }
+}
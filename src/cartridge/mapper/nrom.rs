@@ -0,0 +1,117 @@
+/* https://www.nesdev.org/wiki/NROM
+* No bank switching. 16K or 32K of PRG ROM at $8000, with 16K boards mirrored into
+* $C000, and 8K of CHR. Mirroring is soldered on the board. Family Basic has PRG RAM at
+* $6000, other games leave that range unconnected.
+*/
+
+use super::{super::Cartridge, Chr, Mapper, Mirroring};
+
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    pub fn new(cartridge: Cartridge) -> Self {
+        let header = &cartridge.header;
+        Nrom {
+            prg_ram: vec![0; header.prg_ram_size + header.prg_nvram_size],
+            chr: Chr::new(&cartridge),
+            mirroring: header.mirroring,
+            prg_rom: cartridge.prg_rom,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                Some(self.prg_ram[(address - 0x6000) as usize % self.prg_ram.len()])
+            }
+            0x8000..=0xFFFF => Some(self.prg_rom[(address - 0x8000) as usize % self.prg_rom.len()]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        if let 0x6000..=0x7FFF = address {
+            if !self.prg_ram.is_empty() {
+                let length = self.prg_ram.len();
+                self.prg_ram[(address - 0x6000) as usize % length] = value;
+            }
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        self.chr.read(address as usize)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(address as usize, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn battery_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::test_cartridge, Mapper, Mirroring, Nrom};
+
+    fn windows(nrom: &Nrom) -> [Option<u8>; 4] {
+        [0x8000, 0xA000, 0xC000, 0xE000].map(|address| nrom.cpu_peek(address))
+    }
+
+    #[test]
+    fn prg_rom() {
+        // NROM-128 shows its 16K twice
+        let nrom = Nrom::new(test_cartridge(0, 0, 0x4000, 0x2000));
+        assert_eq!(windows(&nrom), [0, 1, 0, 1].map(Some));
+        let nrom = Nrom::new(test_cartridge(0, 0, 0x8000, 0x2000));
+        assert_eq!(windows(&nrom), [0, 1, 2, 3].map(Some));
+    }
+
+    #[test]
+    fn prg_ram() {
+        let mut nrom = Nrom::new(test_cartridge(0, 0, 0x8000, 0x2000));
+        nrom.cpu_write(0x7FFF, 0x42);
+        assert_eq!(nrom.cpu_peek(0x7FFF), Some(0x42));
+        // Writes to ROM go nowhere
+        nrom.cpu_write(0x8000, 0x42);
+        assert_eq!(nrom.cpu_peek(0x8000), Some(0));
+        // Without any RAM $6000-$7FFF is open bus
+        let mut cartridge = test_cartridge(0, 0, 0x8000, 0x2000);
+        cartridge.header.prg_ram_size = 0;
+        let mut nrom = Nrom::new(cartridge);
+        nrom.cpu_write(0x6000, 0x42);
+        assert_eq!(nrom.cpu_peek(0x6000), None);
+    }
+
+    #[test]
+    fn chr() {
+        let mut rom = Nrom::new(test_cartridge(0, 0, 0x8000, 0x2000));
+        rom.ppu_write(0x0400, 0x42);
+        assert_eq!(rom.ppu_peek(0x0400), 1);
+        let mut ram = Nrom::new(test_cartridge(0, 0, 0x8000, 0));
+        ram.ppu_write(0x0400, 0x42);
+        assert_eq!(ram.ppu_peek(0x0400), 0x42);
+    }
+
+    // Soldered on the board, so it's whatever the header says
+    #[test]
+    fn mirroring() {
+        let mut cartridge = test_cartridge(0, 0, 0x8000, 0x2000);
+        cartridge.header.mirroring = Mirroring::Vertical;
+        assert_eq!(Nrom::new(cartridge).mirroring(), Mirroring::Vertical);
+        let nrom = Nrom::new(test_cartridge(0, 0, 0x8000, 0x2000));
+        assert_eq!(nrom.mirroring(), Mirroring::Horizontal);
+    }
+}
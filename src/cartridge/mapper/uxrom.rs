@@ -0,0 +1,97 @@
+/* https://www.nesdev.org/wiki/UxROM
+* Any write to $8000-$FFFF picks the 16K PRG bank at $8000, the last bank is fixed at
+* $C000. CHR is 8K of RAM and mirroring is soldered on the board.
+*/
+
+use super::{super::Cartridge, bus_conflicts, Chr, Mapper, Mirroring};
+
+pub struct Uxrom {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+    prg_bank: u8,
+}
+
+impl Uxrom {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Uxrom {
+            chr: Chr::new(&cartridge),
+            mirroring: cartridge.header.mirroring,
+            bus_conflicts: bus_conflicts(cartridge.header.submapper, true),
+            prg_bank: 0,
+            prg_rom: cartridge.prg_rom,
+        }
+    }
+
+    fn prg_address(&self, address: u16) -> usize {
+        let bank = if address < 0xC000 {
+            self.prg_bank as usize
+        } else {
+            (self.prg_rom.len() / 0x4000).saturating_sub(1)
+        };
+        (bank * 0x4000 + (address & 0x3FFF) as usize) % self.prg_rom.len()
+    }
+}
+
+impl Mapper for Uxrom {
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_address(address)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        if address >= 0x8000 {
+            self.prg_bank = if self.bus_conflicts {
+                value & self.prg_rom[self.prg_address(address)]
+            } else {
+                value
+            };
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        self.chr.read(address as usize)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(address as usize, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::test_cartridge, Mapper, Uxrom};
+
+    fn windows(uxrom: &Uxrom) -> [Option<u8>; 4] {
+        [0x8000, 0xA000, 0xC000, 0xE000].map(|address| uxrom.cpu_peek(address))
+    }
+
+    #[test]
+    fn prg_banks() {
+        let mut uxrom = Uxrom::new(test_cartridge(2, 0, 0x20000, 0));
+        assert_eq!(windows(&uxrom), [0, 1, 14, 15].map(Some));
+        uxrom.cpu_write(0xE000, 5);
+        assert_eq!(windows(&uxrom), [10, 11, 14, 15].map(Some));
+        // Under 16K the fixed bank is the one there is
+        let small = Uxrom::new(test_cartridge(2, 0, 0x2000, 0));
+        assert_eq!(windows(&small), [0, 0, 0, 0].map(Some));
+    }
+
+    // The value written is ANDed with the ROM byte under it, $0E at $C000, unless NES 2.0
+    // submapper 1 says the board doesn't have conflicts
+    #[test]
+    fn bus_conflicts() {
+        for (submapper, bank) in [(0, 4), (1, 5), (2, 4)] {
+            let mut uxrom = Uxrom::new(test_cartridge(2, submapper, 0x20000, 0));
+            uxrom.cpu_write(0xC000, 5);
+            assert_eq!(uxrom.cpu_peek(0x8000), Some(bank * 2));
+        }
+    }
+}
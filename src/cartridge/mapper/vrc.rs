@@ -0,0 +1,188 @@
+/* https://www.nesdev.org/wiki/VRC_IRQ
+* Pieces shared by the Konami VRC chips.
+*
+* The chips only have two register select lines, and each board connects them to different
+* CPU address lines. NES 2.0 submappers say which, iNES headers can't, so there both
+* possible lines are ORed together. No game writes to an address that sets one of each.
+*
+* The IRQ counter counts up from the latch and raises an IRQ when it wraps past $FF. It's
+* clocked either every CPU cycle, or once a scanline by a prescaler that takes 3 off 341
+* every CPU cycle, as a scanline is 341 PPU dots and a CPU cycle 3 of them.
+*/
+
+#[derive(Clone, Copy)]
+pub struct Wiring {
+    // Masks of the CPU address lines connected to the chip's A0 and A1
+    a0: u16,
+    a1: u16,
+}
+
+impl Wiring {
+    pub const fn new(a0: u16, a1: u16) -> Self {
+        Wiring { a0, a1 }
+    }
+
+    // Folds an address down to the $x000-$x003 register the chip sees
+    pub fn register(self, address: u16) -> u16 {
+        let a0 = (address & self.a0 != 0) as u16;
+        let a1 = (address & self.a1 != 0) as u16;
+        (address & 0xF000) | a1 << 1 | a0
+    }
+}
+
+const PRESCALER_PERIOD: i16 = 341;
+
+pub struct Irq {
+    latch: u8,
+    counter: u8,
+    prescaler: i16,
+    enabled: bool,
+    // Copied to enabled when the IRQ is acknowledged
+    enable_after_ack: bool,
+    cycle_mode: bool,
+    pending: bool,
+}
+
+impl Irq {
+    pub fn new() -> Self {
+        Irq {
+            latch: 0,
+            counter: 0,
+            prescaler: PRESCALER_PERIOD,
+            enabled: false,
+            enable_after_ack: false,
+            cycle_mode: false,
+            pending: false,
+        }
+    }
+
+    pub fn write_latch(&mut self, value: u8) {
+        self.latch = value;
+    }
+
+    // VRC4 takes the latch a nibble at a time
+    pub fn write_latch_low(&mut self, value: u8) {
+        self.latch = (self.latch & 0xF0) | (value & 0x0F);
+    }
+
+    pub fn write_latch_high(&mut self, value: u8) {
+        self.latch = (self.latch & 0x0F) | (value << 4);
+    }
+
+    // .....MEA cycle mode, enable, enable after acknowledge
+    pub fn write_control(&mut self, value: u8) {
+        self.enable_after_ack = value & 0x01 != 0;
+        self.enabled = value & 0x02 != 0;
+        self.cycle_mode = value & 0x04 != 0;
+        self.pending = false;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = PRESCALER_PERIOD;
+        }
+    }
+
+    pub fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    pub fn pending(&self) -> bool {
+        self.pending
+    }
+
+    // Once every CPU cycle
+    pub fn clock(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.cycle_mode {
+            self.clock_counter();
+        } else {
+            self.prescaler -= 3;
+            if self.prescaler <= 0 {
+                self.prescaler += PRESCALER_PERIOD;
+                self.clock_counter();
+            }
+        }
+    }
+
+    fn clock_counter(&mut self) {
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Irq, Wiring};
+
+    #[test]
+    fn wiring() {
+        // VRC4e has the chip's A0 on A2 and A1 on A3
+        let vrc4e = Wiring::new(0x04, 0x08);
+        assert_eq!(vrc4e.register(0xB000), 0xB000);
+        assert_eq!(vrc4e.register(0xB004), 0xB001);
+        assert_eq!(vrc4e.register(0xB008), 0xB002);
+        assert_eq!(vrc4e.register(0xB00C), 0xB003);
+        // Lines that aren't connected are ignored
+        assert_eq!(vrc4e.register(0xB003), 0xB000);
+        // Boards like VRC4b swap the two lines
+        let vrc4b = Wiring::new(0x02, 0x01);
+        assert_eq!(vrc4b.register(0x9001), 0x9002);
+        assert_eq!(vrc4b.register(0x9002), 0x9001);
+        // iNES mapper 23 can't tell VRC4f from VRC4e, either line selects the register
+        let ines = Wiring::new(0x05, 0x0A);
+        assert_eq!(ines.register(0xF002), 0xF002);
+        assert_eq!(ines.register(0xF008), 0xF002);
+        assert_eq!(ines.register(0xF001), 0xF001);
+        assert_eq!(ines.register(0xF004), 0xF001);
+    }
+
+    // Cycles until the IRQ fires, acknowledging it again
+    fn cycles_to_irq(irq: &mut Irq) -> usize {
+        let mut cycles = 0;
+        while !irq.pending() {
+            irq.clock();
+            cycles += 1;
+        }
+        irq.acknowledge();
+        cycles
+    }
+
+    #[test]
+    fn cycle_mode() {
+        let mut irq = Irq::new();
+        irq.write_latch(0xFE);
+        irq.write_control(0x07);
+        assert_eq!(cycles_to_irq(&mut irq), 2);
+        // Reloaded from the latch, and still enabled through the enable after acknowledge
+        assert_eq!(cycles_to_irq(&mut irq), 2);
+        irq.write_control(0x06);
+        irq.clock();
+        irq.clock();
+        irq.acknowledge();
+        for _ in 0..0x200 {
+            irq.clock();
+        }
+        assert!(!irq.pending());
+    }
+
+    // The prescaler spreads 341 dots over whole CPU cycles, so three scanlines are exactly
+    // 341 cycles even though each takes 113 or 114
+    #[test]
+    fn prescaler() {
+        let mut irq = Irq::new();
+        irq.write_latch(0xFF);
+        irq.write_control(0x03);
+        let lines = [(); 3].map(|_| cycles_to_irq(&mut irq));
+        assert_eq!(lines, [114, 114, 113]);
+        // A latch of $FE counts two scanlines
+        irq.write_latch(0xFE);
+        irq.write_control(0x03);
+        assert_eq!(cycles_to_irq(&mut irq), 228);
+    }
+}
@@ -0,0 +1,225 @@
+/* https://www.nesdev.org/wiki/VRC2_and_VRC4
+* VRC4 is a VRC2 with more bank bits, a second PRG mode and the IRQ counter. Registers
+* after folding the address lines, see vrc.rs:
+*
+* $8000-$8003 PRG bank at $8000, or $C000 in PRG mode 1
+* $9000-$9001 Mirroring     VRC2 vertical or horizontal, VRC4 also either single screen
+* $9002-$9003 PRG mode      bit 1, VRC4 only
+* $A000-$A003 PRG bank at $A000
+* $B000-$E003 CHR banks     two registers per 1K bank, the low then the high nibble
+* $F000-$F003 IRQ           latch low nibble, latch high nibble, control, acknowledge
+*
+* The second last and last 8K PRG banks are fixed at $C000 (or $8000) and $E000. VRC2a
+* doesn't connect the lowest CHR bank bit, so its banks are half what's written.
+*
+* Mapper 21   VRC4a A1 A2, VRC4c A6 A7
+* Mapper 22   VRC2a A1 A0
+* Mapper 23   VRC4f A0 A1, VRC4e A2 A3, VRC2b A0 A1
+* Mapper 25   VRC4b A1 A0, VRC4d A3 A2, VRC2c A1 A0
+* with the lines connected to the chip's A0 then A1.
+*/
+
+use super::{
+    super::Cartridge,
+    vrc::{Irq, Wiring},
+    Chr, Mapper, Mirroring,
+};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Chip {
+    Vrc2a,
+    Vrc2,
+    Vrc4,
+}
+
+pub struct Vrc4 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    chip: Chip,
+    wiring: Wiring,
+    prg_banks: [u8; 2],
+    prg_mode: bool,
+    chr_banks: [u16; 8],
+    mirroring: u8,
+    // VRC2 boards without RAM have a one bit latch at $6000-$6FFF instead
+    latch: u8,
+    irq: Irq,
+}
+
+impl Vrc4 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        let header = &cartridge.header;
+        let (chip, wiring) = match (header.mapper, header.submapper) {
+            (21, 1) => (Chip::Vrc4, Wiring::new(0x02, 0x04)),
+            (21, 2) => (Chip::Vrc4, Wiring::new(0x40, 0x80)),
+            (21, _) => (Chip::Vrc4, Wiring::new(0x42, 0x84)),
+            (22, _) => (Chip::Vrc2a, Wiring::new(0x02, 0x01)),
+            (23, 1) => (Chip::Vrc4, Wiring::new(0x01, 0x02)),
+            (23, 2) => (Chip::Vrc4, Wiring::new(0x04, 0x08)),
+            (23, 3) => (Chip::Vrc2, Wiring::new(0x01, 0x02)),
+            (23, _) => (Chip::Vrc4, Wiring::new(0x05, 0x0A)),
+            (25, 1) => (Chip::Vrc4, Wiring::new(0x02, 0x01)),
+            (25, 2) => (Chip::Vrc4, Wiring::new(0x08, 0x04)),
+            (25, 3) => (Chip::Vrc2, Wiring::new(0x02, 0x01)),
+            (_, _) => (Chip::Vrc4, Wiring::new(0x0A, 0x05)),
+        };
+        Vrc4 {
+            prg_ram: vec![0; header.prg_ram_size + header.prg_nvram_size],
+            chr: Chr::new(&cartridge),
+            chip,
+            wiring,
+            prg_banks: [0; 2],
+            prg_mode: false,
+            chr_banks: [0; 8],
+            mirroring: 0,
+            latch: 0,
+            irq: Irq::new(),
+            prg_rom: cartridge.prg_rom,
+        }
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        let register = self.wiring.register(address);
+        let vrc4 = self.chip == Chip::Vrc4;
+        match register {
+            0x8000..=0x8003 => self.prg_banks[0] = value & 0x1F,
+            0x9000..=0x9001 => self.mirroring = value & if vrc4 { 0x03 } else { 0x01 },
+            0x9002..=0x9003 if vrc4 => self.prg_mode = value & 0x02 != 0,
+            0x9002..=0x9003 => self.mirroring = value & 0x01,
+            0xA000..=0xA003 => self.prg_banks[1] = value & 0x1F,
+            0xB000..=0xEFFF => {
+                let index = (((register - 0xB000) >> 12) * 2 + ((register & 0x03) >> 1)) as usize;
+                let bank = self.chr_banks[index];
+                self.chr_banks[index] = if register & 0x01 == 0 {
+                    (bank & 0x1F0) | (value & 0x0F) as u16
+                } else {
+                    (bank & 0x0F) | ((value & 0x1F) as u16) << 4
+                };
+            }
+            0xF000 if vrc4 => self.irq.write_latch_low(value),
+            0xF001 if vrc4 => self.irq.write_latch_high(value),
+            0xF002 if vrc4 => self.irq.write_control(value),
+            0xF003 if vrc4 => self.irq.acknowledge(),
+            _ => (),
+        }
+    }
+
+    fn prg_address(&self, address: u16) -> usize {
+        // The fixed banks count from the end, wrapping around on ROMs with fewer than two
+        let banks = (self.prg_rom.len() / 0x2000).max(1);
+        let last = banks - 1;
+        let second_last = (last + banks - 1) % banks;
+        let bank = match (address >> 13) & 0x03 {
+            0 if self.prg_mode => second_last,
+            0 => self.prg_banks[0] as usize,
+            1 => self.prg_banks[1] as usize,
+            2 if self.prg_mode => self.prg_banks[0] as usize,
+            2 => second_last,
+            _ => last,
+        };
+        (bank * 0x2000 + (address & 0x1FFF) as usize) % self.prg_rom.len()
+    }
+
+    fn chr_address(&self, address: u16) -> usize {
+        let bank = self.chr_banks[(address >> 10) as usize & 0x07];
+        let bank = match self.chip {
+            Chip::Vrc2a => bank >> 1,
+            Chip::Vrc2 => bank & 0xFF,
+            Chip::Vrc4 => bank,
+        };
+        bank as usize * 0x400 + (address & 0x3FF) as usize
+    }
+}
+
+impl Mapper for Vrc4 {
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                Some(self.prg_ram[(address - 0x6000) as usize % self.prg_ram.len()])
+            }
+            // Only bit 0 is driven on the real board, the others read back as 0 here
+            0x6000..=0x6FFF if self.chip != Chip::Vrc4 => Some(self.latch),
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_address(address)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                let length = self.prg_ram.len();
+                self.prg_ram[(address - 0x6000) as usize % length] = value;
+            }
+            0x6000..=0x6FFF if self.chip != Chip::Vrc4 => self.latch = value & 0x01,
+            0x8000..=0xFFFF => self.write_register(address, value),
+            _ => (),
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        self.chr.read(self.chr_address(address))
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        let address = self.chr_address(address);
+        self.chr.write(address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.mirroring {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn cpu_clock(&mut self) {
+        self.irq.clock();
+    }
+
+    fn battery_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::test_cartridge, Mapper, Vrc4};
+
+    fn windows(vrc4: &Vrc4) -> [Option<u8>; 4] {
+        [0x8000, 0xA000, 0xC000, 0xE000].map(|address| vrc4.cpu_peek(address))
+    }
+
+    #[test]
+    fn prg_banks() {
+        // VRC4e, registers on A2 and A3
+        let mut vrc4 = Vrc4::new(test_cartridge(23, 2, 0x20000, 0x20000));
+        vrc4.cpu_write(0x8000, 3);
+        vrc4.cpu_write(0xA000, 4);
+        assert_eq!(windows(&vrc4), [3, 4, 14, 15].map(Some));
+        vrc4.cpu_write(0x9008, 0x02);
+        assert_eq!(windows(&vrc4), [14, 4, 3, 15].map(Some));
+        // With a single 8K bank both fixed banks are that one
+        let small = Vrc4::new(test_cartridge(23, 2, 0x2000, 0x2000));
+        assert_eq!(windows(&small), [0, 0, 0, 0].map(Some));
+    }
+
+    #[test]
+    fn chr_banks() {
+        let mut vrc4 = Vrc4::new(test_cartridge(23, 2, 0x20000, 0x40000));
+        // The low then the high nibble of the bank at $0400
+        vrc4.cpu_write(0xB008, 0x0A);
+        vrc4.cpu_write(0xB00C, 0x02);
+        assert_eq!(vrc4.ppu_peek(0x0400), 0x2A);
+        // VRC2a drops the lowest bit
+        let mut vrc2a = Vrc4::new(test_cartridge(22, 0, 0x20000, 0x20000));
+        vrc2a.cpu_write(0xB000, 0x06);
+        assert_eq!(vrc2a.ppu_peek(0x0000), 3);
+    }
+}
@@ -0,0 +1,218 @@
+/* https://www.nesdev.org/wiki/VRC6
+* Registers after folding the address lines, VRC6a (mapper 24) connects A0 and A1 and
+* VRC6b (mapper 26) swaps them.
+*
+* $8000-$8003 16K PRG bank at $8000
+* $B003       Banking       W..NMMDD PRG RAM enable, nametable mirroring, CHR mode
+* $C000-$C003 8K PRG bank at $C000, the last bank is fixed at $E000
+* $D000-$E003 CHR banks R0-R7
+* $F000-$F002 IRQ           latch, control, acknowledge
+*
+* CHR mode 0 has eight 1K banks, mode 1 four 2K banks from R0-R3, and modes 2 and 3 1K
+* banks from R0-R3 at $0000 and 2K banks from R4-R5 at $1000. In the 2K banks the lowest
+* bank bit comes from PPU A10.
+*
+* The rest of $9000-$B002 is the expansion audio, which isn't emulated, and so aren't the
+* CHR ROM nametables bit 4 of $B003 can turn on.
+*/
+
+use super::{
+    super::Cartridge,
+    vrc::{Irq, Wiring},
+    Chr, Mapper, Mirroring,
+};
+
+pub struct Vrc6 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    wiring: Wiring,
+    prg_bank_16k: u8,
+    prg_bank_8k: u8,
+    chr_banks: [u8; 8],
+    banking: u8,
+    irq: Irq,
+}
+
+impl Vrc6 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        let header = &cartridge.header;
+        let wiring = if header.mapper == 26 {
+            Wiring::new(0x02, 0x01)
+        } else {
+            Wiring::new(0x01, 0x02)
+        };
+        Vrc6 {
+            prg_ram: vec![0; header.prg_ram_size + header.prg_nvram_size],
+            chr: Chr::new(&cartridge),
+            wiring,
+            prg_bank_16k: 0,
+            prg_bank_8k: 0,
+            chr_banks: [0; 8],
+            banking: 0,
+            irq: Irq::new(),
+            prg_rom: cartridge.prg_rom,
+        }
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        match self.wiring.register(address) {
+            0x8000..=0x8003 => self.prg_bank_16k = value,
+            0xB003 => self.banking = value,
+            0xC000..=0xC003 => self.prg_bank_8k = value,
+            register @ 0xD000..=0xE003 => {
+                let index = ((register - 0xD000) >> 12) * 4 + (register & 0x03);
+                self.chr_banks[index as usize] = value;
+            }
+            0xF000 => self.irq.write_latch(value),
+            0xF001 => self.irq.write_control(value),
+            0xF002 => self.irq.acknowledge(),
+            _ => (),
+        }
+    }
+
+    fn prg_address(&self, address: u16) -> usize {
+        // The fixed bank counts from the end, wrapping around on ROMs with less than 8K
+        let banks = (self.prg_rom.len() / 0x2000).max(1);
+        let address = match address {
+            0x8000..=0xBFFF => self.prg_bank_16k as usize * 0x4000 + (address & 0x3FFF) as usize,
+            0xC000..=0xDFFF => self.prg_bank_8k as usize * 0x2000 + (address & 0x1FFF) as usize,
+            _ => (banks - 1) * 0x2000 + (address & 0x1FFF) as usize,
+        };
+        address % self.prg_rom.len()
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        !self.prg_ram.is_empty() && self.banking & 0x80 != 0
+    }
+
+    fn chr_address(&self, address: u16) -> usize {
+        let slot = (address >> 10) as usize & 0x07;
+        let a10 = slot as u8 & 0x01;
+        let bank = match (self.banking & 0x03, slot) {
+            (0, _) => self.chr_banks[slot],
+            (1, _) => (self.chr_banks[slot >> 1] & 0xFE) | a10,
+            (_, 0..=3) => self.chr_banks[slot],
+            (_, _) => (self.chr_banks[4 + ((slot - 4) >> 1)] & 0xFE) | a10,
+        };
+        bank as usize * 0x400 + (address & 0x3FF) as usize
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                Some(self.prg_ram[(address - 0x6000) as usize % self.prg_ram.len()])
+            }
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_address(address)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                let length = self.prg_ram.len();
+                self.prg_ram[(address - 0x6000) as usize % length] = value;
+            }
+            0x8000..=0xFFFF => self.write_register(address, value),
+            _ => (),
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        self.chr.read(self.chr_address(address))
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        let address = self.chr_address(address);
+        self.chr.write(address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match (self.banking >> 2) & 0x03 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn cpu_clock(&mut self) {
+        self.irq.clock();
+    }
+
+    fn battery_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::test_cartridge, Mapper, Vrc6};
+
+    fn windows(vrc6: &Vrc6) -> [Option<u8>; 4] {
+        [0x8000, 0xA000, 0xC000, 0xE000].map(|address| vrc6.cpu_peek(address))
+    }
+
+    // The 1K bank in each slot of the pattern tables
+    fn chr(vrc6: &Vrc6) -> [u8; 8] {
+        std::array::from_fn(|slot| vrc6.ppu_peek(slot as u16 * 0x400))
+    }
+
+    #[test]
+    fn prg_banks() {
+        let mut vrc6 = Vrc6::new(test_cartridge(24, 0, 0x20000, 0x10000));
+        vrc6.cpu_write(0x8000, 2);
+        vrc6.cpu_write(0xC000, 9);
+        assert_eq!(windows(&vrc6), [4, 5, 9, 15].map(Some));
+        // With a single 8K bank the fixed bank is that one
+        let small = Vrc6::new(test_cartridge(24, 0, 0x2000, 0x2000));
+        assert_eq!(windows(&small), [0, 0, 0, 0].map(Some));
+    }
+
+    // The 2K banks take their lowest bit from PPU A10 instead of the register
+    #[test]
+    fn chr_modes() {
+        let mut vrc6 = Vrc6::new(test_cartridge(24, 0, 0x20000, 0x10000));
+        for (register, address) in [
+            0xD000, 0xD001, 0xD002, 0xD003, 0xE000, 0xE001, 0xE002, 0xE003,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            vrc6.cpu_write(address, 0x21 + 2 * register as u8);
+        }
+        for (mode, expected) in [
+            (0, [0x21, 0x23, 0x25, 0x27, 0x29, 0x2B, 0x2D, 0x2F]),
+            (1, [0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27]),
+            (2, [0x21, 0x23, 0x25, 0x27, 0x28, 0x29, 0x2A, 0x2B]),
+            (3, [0x21, 0x23, 0x25, 0x27, 0x28, 0x29, 0x2A, 0x2B]),
+        ] {
+            vrc6.cpu_write(0xB003, mode);
+            assert_eq!(chr(&vrc6), expected, "CHR mode {mode}");
+        }
+    }
+
+    // VRC6b has A0 and A1 the other way around
+    #[test]
+    fn vrc6b() {
+        let mut vrc6a = Vrc6::new(test_cartridge(24, 0, 0x20000, 0x10000));
+        let mut vrc6b = Vrc6::new(test_cartridge(26, 0, 0x20000, 0x10000));
+        for vrc6 in [&mut vrc6a, &mut vrc6b] {
+            vrc6.cpu_write(0xD001, 5);
+            vrc6.cpu_write(0xD002, 6);
+        }
+        assert_eq!([chr(&vrc6a)[1], chr(&vrc6a)[2]], [5, 6]);
+        assert_eq!([chr(&vrc6b)[1], chr(&vrc6b)[2]], [6, 5]);
+        // $B003 has both lines set, so it's the same on both
+        vrc6b.cpu_write(0xB003, 0x80);
+        vrc6b.cpu_write(0x6000, 0x42);
+        assert_eq!(vrc6b.cpu_peek(0x6000), Some(0x42));
+    }
+}
@@ -0,0 +1,177 @@
+/* https://www.nesdev.org/wiki/VRC7
+* The second register at each address is picked by A4 on VRC7a (Lagrange Point) and A3
+* on VRC7b (Tiny Toon Adventures 2).
+*
+* $8000 PRG bank at $8000   $8010 PRG bank at $A000
+* $9000 PRG bank at $C000, the last bank is fixed at $E000
+* $A000-$D010 CHR banks     1K each, two per address
+* $E000 Control             WS....MM PRG RAM enable, audio reset, mirroring
+* $E010 IRQ latch
+* $F000 IRQ control         $F010 IRQ acknowledge
+*
+* $9010 and $9030 are the FM synthesizer, which isn't emulated.
+*/
+
+use super::{super::Cartridge, vrc::Irq, Chr, Mapper, Mirroring};
+
+pub struct Vrc7 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    // Mask of the address line that picks the second register
+    select: u16,
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    control: u8,
+    irq: Irq,
+}
+
+impl Vrc7 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        let header = &cartridge.header;
+        let select = match header.submapper {
+            1 => 0x08,
+            2 => 0x10,
+            _ => 0x18,
+        };
+        Vrc7 {
+            prg_ram: vec![0; header.prg_ram_size + header.prg_nvram_size],
+            chr: Chr::new(&cartridge),
+            select,
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            control: 0,
+            irq: Irq::new(),
+            prg_rom: cartridge.prg_rom,
+        }
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        let second = (address & self.select != 0) as usize;
+        match address & 0xF000 {
+            0x8000 => self.prg_banks[second] = value & 0x3F,
+            0x9000 if second == 0 => self.prg_banks[2] = value & 0x3F,
+            0xA000..=0xD000 => {
+                let index = ((address - 0xA000) >> 12) as usize * 2 + second;
+                self.chr_banks[index] = value;
+            }
+            0xE000 if second == 0 => self.control = value,
+            0xE000 => self.irq.write_latch(value),
+            0xF000 if second == 0 => self.irq.write_control(value),
+            0xF000 => self.irq.acknowledge(),
+            _ => (),
+        }
+    }
+
+    fn prg_address(&self, address: u16) -> usize {
+        // The fixed bank counts from the end, wrapping around on ROMs with less than 8K
+        let banks = (self.prg_rom.len() / 0x2000).max(1);
+        let bank = match (address >> 13) & 0x03 {
+            3 => banks - 1,
+            slot => self.prg_banks[slot as usize] as usize,
+        };
+        (bank * 0x2000 + (address & 0x1FFF) as usize) % self.prg_rom.len()
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        !self.prg_ram.is_empty() && self.control & 0x80 != 0
+    }
+
+    fn chr_address(&self, address: u16) -> usize {
+        let bank = self.chr_banks[(address >> 10) as usize & 0x07];
+        bank as usize * 0x400 + (address & 0x3FF) as usize
+    }
+}
+
+impl Mapper for Vrc7 {
+    fn cpu_peek(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                Some(self.prg_ram[(address - 0x6000) as usize % self.prg_ram.len()])
+            }
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_address(address)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                let length = self.prg_ram.len();
+                self.prg_ram[(address - 0x6000) as usize % length] = value;
+            }
+            0x8000..=0xFFFF => self.write_register(address, value),
+            _ => (),
+        }
+    }
+
+    fn ppu_peek(&self, address: u16) -> u8 {
+        self.chr.read(self.chr_address(address))
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        let address = self.chr_address(address);
+        self.chr.write(address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0x03 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn cpu_clock(&mut self) {
+        self.irq.clock();
+    }
+
+    fn battery_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::test_cartridge, Mapper, Vrc7};
+
+    fn windows(vrc7: &Vrc7) -> [Option<u8>; 4] {
+        [0x8000, 0xA000, 0xC000, 0xE000].map(|address| vrc7.cpu_peek(address))
+    }
+
+    #[test]
+    fn prg_banks() {
+        let mut vrc7 = Vrc7::new(test_cartridge(85, 0, 0x20000, 0x10000));
+        vrc7.cpu_write(0x8000, 3);
+        vrc7.cpu_write(0x8010, 4);
+        vrc7.cpu_write(0x9000, 5);
+        assert_eq!(windows(&vrc7), [3, 4, 5, 15].map(Some));
+        // With a single 8K bank the fixed bank is that one
+        let small = Vrc7::new(test_cartridge(85, 0, 0x2000, 0x2000));
+        assert_eq!(windows(&small), [0, 0, 0, 0].map(Some));
+    }
+
+    // VRC7b picks the second register with A3, VRC7a with A4, and without a submapper
+    // either one does
+    #[test]
+    fn select_lines() {
+        for (submapper, prg, chr) in [
+            (0, [0, 3], [0, 5]),
+            (1, [3, 2], [0, 5]),
+            (2, [2, 3], [5, 0]),
+        ] {
+            let mut vrc7 = Vrc7::new(test_cartridge(85, submapper, 0x20000, 0x10000));
+            vrc7.cpu_write(0x8008, 2);
+            vrc7.cpu_write(0x8010, 3);
+            vrc7.cpu_write(0xA008, 5);
+            assert_eq!(windows(&vrc7)[..2], prg.map(Some), "submapper {submapper}");
+            let banks = [0x0000, 0x0400].map(|address| vrc7.ppu_peek(address));
+            assert_eq!(banks, chr, "submapper {submapper}");
+        }
+    }
+}
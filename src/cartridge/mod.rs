@@ -0,0 +1,87 @@
+mod battery;
+mod ines;
+pub mod mapper;
+
+use std::{fs, path::Path};
+
+pub use battery::{save_path, Battery};
+pub use ines::{Format, Header, RomError};
+pub use mapper::Mapper;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    // Every nametable address shows the same one of the two nametables
+    SingleScreenLower,
+    SingleScreenUpper,
+    // The cartridge provides the other two nametables
+    FourScreen,
+    // Which of the two nametables each of the four shows, for boards that can pick freely
+    Custom([u8; 4]),
+}
+
+impl Mirroring {
+    // The 1K page of nametable memory a $2000-$2FFF address lands in. Only FourScreen
+    // goes past the two pages the console has.
+    pub fn page(self, address: u16) -> usize {
+        let quadrant = ((address >> 10) & 0x03) as usize;
+        match self {
+            Mirroring::Horizontal => quadrant >> 1,
+            Mirroring::Vertical => quadrant & 0x01,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+            Mirroring::FourScreen => quadrant,
+            Mirroring::Custom(pages) => pages[quadrant] as usize & 0x01,
+        }
+    }
+}
+
+// Everything in a .nes file
+pub struct Cartridge {
+    pub header: Header,
+    // 512 bytes that get loaded at $7000, used by some hacked ROMs
+    pub trainer: Option<Vec<u8>>,
+    pub prg_rom: Vec<u8>,
+    // Empty when the board has CHR RAM instead
+    pub chr_rom: Vec<u8>,
+}
+
+impl Cartridge {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, RomError> {
+        Cartridge::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, RomError> {
+        ines::parse(bytes)
+    }
+
+    // Sets up the board the cartridge uses, ready to be plugged into the bus
+    pub fn into_mapper(mut self) -> Result<Box<dyn Mapper>, RomError> {
+        let trainer = self.trainer.take();
+        let mut mapper = mapper::create(self)?;
+        // $7000 is $1000 into the first 8K of PRG RAM. Boards without any drop the trainer.
+        if let (Some(trainer), Some(ram)) = (trainer, mapper.battery_ram()) {
+            if let Some(ram) = ram.get_mut(0x1000..0x1000 + trainer.len()) {
+                ram.copy_from_slice(&trainer);
+            }
+        }
+        Ok(mapper)
+    }
+
+    // Same as into_mapper, except that when the cartridge has a battery its RAM is kept in
+    // a save file named after the ROM at rom, in saves if given or next to the ROM otherwise
+    pub fn into_mapper_with_save(
+        self,
+        rom: &Path,
+        saves: Option<&Path>,
+    ) -> Result<Box<dyn Mapper>, RomError> {
+        let battery = self.header.battery;
+        let mapper = self.into_mapper()?;
+        if !battery {
+            return Ok(mapper);
+        }
+        let battery = Battery::new(mapper, save_path(rom, saves)).map_err(RomError::Save)?;
+        Ok(Box::new(battery))
+    }
+}
@@ -0,0 +1,61 @@
+/* https://www.nesdev.org/wiki/Standard_controller
+* Writing 1 to $4016 latches the buttons into a shift register that is read out one bit at
+* a time through $4016 or $4017, in the order of the constants below. While the strobe bit
+* stays 1 the register keeps reloading, so every read returns A. After all 8 buttons have
+* been read an official controller returns 1s.
+*/
+
+pub const A: u8 = 0x01;
+pub const B: u8 = 0x02;
+pub const SELECT: u8 = 0x04;
+pub const START: u8 = 0x08;
+pub const UP: u8 = 0x10;
+pub const DOWN: u8 = 0x20;
+pub const LEFT: u8 = 0x40;
+pub const RIGHT: u8 = 0x80;
+
+pub struct Controller {
+    buttons: u8,
+    shift: u8,
+    strobe: bool,
+}
+
+impl Controller {
+    pub fn new() -> Self {
+        Controller {
+            buttons: 0,
+            shift: 0,
+            strobe: false,
+        }
+    }
+
+    // Buttons currently held down, a set bit means pressed
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.buttons = buttons;
+    }
+
+    pub fn write_strobe(&mut self, value: u8) {
+        self.strobe = value & 1 != 0;
+        if self.strobe {
+            self.shift = self.buttons;
+        }
+    }
+
+    // The next button in bit 0
+    pub fn read(&mut self) -> u8 {
+        let bit = self.peek();
+        if !self.strobe {
+            self.shift = (self.shift >> 1) | 0x80;
+        }
+        bit
+    }
+
+    // What the next read returns, without moving on to the next button
+    pub fn peek(&self) -> u8 {
+        if self.strobe {
+            self.buttons & 1
+        } else {
+            self.shift & 1
+        }
+    }
+}
@@ -1,49 +1,244 @@
+/* https://www.nesdev.org/wiki/CPU_memory_map
+* Anything that doesn't drive the data bus on a read leaves the last value that was on it,
+* so unmapped addresses and the unused bits of some registers read back as open bus.
+* https://www.nesdev.org/wiki/Open_bus_behavior
+*/
+
+use crate::apu::Apu;
+use crate::cartridge::Mapper;
+use crate::controller::Controller;
+use crate::mos6502::Bus;
+use crate::ppu::Ppu;
+
 pub struct CpuMemory {
     work_memory: [u8; 2048],
-    ppu_ctrl: [u8; 8],
+    ppu: Ppu,
+    apu: Apu,
+    controllers: [Controller; 2],
+    cartridge: Option<Box<dyn Mapper>>,
+    // Last value on the data bus
+    open_bus: u8,
+    // Set by a write to $4014 until the CPU picks it up with take_dma
+    dma: bool,
 }
 
 impl CpuMemory {
+    pub fn new() -> Self {
+        CpuMemory {
+            work_memory: [0; 2048],
+            ppu: Ppu::new(),
+            apu: Apu::new(),
+            controllers: [Controller::new(), Controller::new()],
+            cartridge: None,
+            open_bus: 0,
+            dma: false,
+        }
+    }
+
     #[inline]
-    pub fn read(&self, address: u16) -> u8 {
-        match address {
+    pub fn read(&mut self, address: u16) -> u8 {
+        let value = match address {
             // Work Memory & Mirrors
             0x0000..=0x1FFF => self.work_memory[(address % 2048) as usize],
             // PPU Ctrl Registers & Mirrors
-            0x2000..=0x3FFF => self.ppu_ctrl[(address % 8) as usize],
-            //APU and IO registers
-            0x4000..=0x4017 => {
-                todo!("APU and IO registers")
-            }
-            //Cpu Test Mode
-            0x4018..=0x401F => {
-                todo!("CPU test mode")
-            }
-            //Cartridge Read
-            0x4020..=0xFFFF => {
-                todo!("Cartridge read")
-            }
+            0x2000..=0x3FFF => self.ppu.read(address, &mut self.cartridge),
+            // APU status. It's read inside the 2A03 so it doesn't reach the data bus.
+            0x4015 => return self.apu.read_status() | (self.open_bus & 0x20),
+            // Controller ports, only the low bits are driven
+            0x4016 => (self.open_bus & 0xE0) | self.controllers[0].read(),
+            0x4017 => (self.open_bus & 0xE0) | self.controllers[1].read(),
+            // The rest of the APU and IO registers are write only
+            0x4000..=0x4014 => self.open_bus,
+            // CPU test mode registers, disabled on retail consoles
+            0x4018..=0x401F => self.open_bus,
+            // Cartridge space, open bus wherever the board doesn't answer
+            0x4020..=0xFFFF => match &mut self.cartridge {
+                Some(cartridge) => cartridge.cpu_read(address).unwrap_or(self.open_bus),
+                None => self.open_bus,
+            },
+        };
+        self.open_bus = value;
+        value
+    }
+
+    // Same as read but without any side effects, for debuggers and trace logs. Reading
+    // registers acknowledges interrupts and moves controllers on to the next button, none
+    // of which happens here, and open bus is left alone.
+    pub fn peek(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1FFF => self.work_memory[(address % 2048) as usize],
+            0x2000..=0x3FFF => self.ppu.peek(address),
+            0x4015 => self.apu.peek_status() | (self.open_bus & 0x20),
+            0x4016 => (self.open_bus & 0xE0) | self.controllers[0].peek(),
+            0x4017 => (self.open_bus & 0xE0) | self.controllers[1].peek(),
+            0x4000..=0x4014 => self.open_bus,
+            0x4018..=0x401F => self.open_bus,
+            0x4020..=0xFFFF => match &self.cartridge {
+                Some(cartridge) => cartridge.cpu_peek(address).unwrap_or(self.open_bus),
+                None => self.open_bus,
+            },
         }
     }
 
     pub fn write(&mut self, address: u16, value: u8) {
+        self.open_bus = value;
         match address {
             // Work Memory & Mirrorsw
             0x0000..=0x1FFF => self.work_memory[(address % 2048) as usize] = value,
             // PPU Ctrl Registers & Mirrors
-            0x2000..=0x3FFF => self.ppu_ctrl[(address % 8) as usize] = value,
-            //APU and IO registers
-            0x4000..=0x4017 => {
-                todo!("APU and IO registers")
+            0x2000..=0x3FFF => self.ppu.write(address, value, &mut self.cartridge),
+            0x4014 => self.oam_dma(value),
+            // Strobes both controllers
+            0x4016 => {
+                for controller in &mut self.controllers {
+                    controller.write_strobe(value);
+                }
             }
+            //APU registers, $4017 is the frame counter
+            0x4000..=0x4013 | 0x4015 | 0x4017 => self.apu.write(address, value),
             //Cpu Test Mode
-            0x4018..=0x401F => {
-                todo!("CPU test mode")
-            }
+            0x4018..=0x401F => (),
             //Cartridge Write
             0x4020..=0xFFFF => {
-                todo!("Cartridge read")
+                if let Some(cartridge) = &mut self.cartridge {
+                    cartridge.cpu_write(address, value);
+                }
             }
         }
     }
+
+    pub fn insert_cartridge(&mut self, cartridge: Box<dyn Mapper>) {
+        self.cartridge = Some(cartridge);
+    }
+
+    pub fn cartridge(&mut self) -> Option<&mut (dyn Mapper + 'static)> {
+        self.cartridge.as_deref_mut()
+    }
+
+    // Whether anything is pulling the CPU's IRQ line
+    pub fn irq(&self) -> bool {
+        self.cartridge
+            .as_ref()
+            .is_some_and(|cartridge| cartridge.irq())
+    }
+
+    pub fn ppu(&mut self) -> &mut Ppu {
+        &mut self.ppu
+    }
+
+    pub fn controller(&mut self, port: usize) -> &mut Controller {
+        &mut self.controllers[port]
+    }
+
+    // Copies a page to OAM through $2004. The CPU is halted while this happens, the copy is
+    // done all at once and the CPU sits out the cycles it would've taken.
+    // https://www.nesdev.org/wiki/PPU_registers#OAMDMA
+    fn oam_dma(&mut self, page: u8) {
+        for low in 0..=0xFF {
+            let value = self.read(u16::from_be_bytes([page, low]));
+            self.write(0x2004, value);
+        }
+        self.dma = true;
+    }
+}
+
+impl Bus for CpuMemory {
+    fn read(&mut self, address: u16) -> u8 {
+        CpuMemory::read(self, address)
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        CpuMemory::write(self, address, value)
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        CpuMemory::peek(self, address)
+    }
+
+    fn tick(&mut self) {
+        self.ppu.tick();
+        if let Some(cartridge) = &mut self.cartridge {
+            cartridge.cpu_clock();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        CpuMemory::irq(self)
+    }
+
+    fn take_dma(&mut self) -> bool {
+        std::mem::take(&mut self.dma)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CpuMemory;
+    use crate::controller::{A, START, UP};
+    use crate::mos6502::Bus;
+
+    #[test]
+    fn open_bus() {
+        let mut memory = CpuMemory::new();
+        memory.write(0x0010, 0x5A);
+        assert_eq!(memory.read(0x0010), 0x5A);
+        // Nothing answers here without a cartridge, and the write only registers read back
+        // whatever was last on the bus
+        assert_eq!(memory.read(0x5000), 0x5A);
+        assert_eq!(memory.read(0x4000), 0x5A);
+        assert_eq!(memory.read(0x4018), 0x5A);
+        memory.write(0x4000, 0xC3);
+        assert_eq!(memory.peek(0x8000), 0xC3);
+        assert_eq!(memory.read(0x8000), 0xC3);
+    }
+
+    // Bit 5 of $4015 isn't driven, and reading it doesn't put anything on the bus
+    #[test]
+    fn apu_status() {
+        let mut memory = CpuMemory::new();
+        memory.write(0x0010, 0xFF);
+        memory.read(0x0010);
+        assert_eq!(memory.read(0x4015) & 0x20, 0x20);
+        assert_eq!(memory.read(0x4015) & 0x20, 0x20);
+        assert_eq!(memory.read(0x5000), 0xFF);
+        memory.write(0x0010, 0x00);
+        assert_eq!(memory.read(0x4015) & 0x20, 0x00);
+    }
+
+    #[test]
+    fn controllers() {
+        let mut memory = CpuMemory::new();
+        memory.controller(0).set_buttons(A | START | UP);
+        memory.controller(1).set_buttons(A);
+        memory.write(0x4016, 1);
+        // The strobe keeps reloading the buttons, so A comes back every time
+        assert_eq!(memory.read(0x4016) & 0x01, 1);
+        assert_eq!(memory.read(0x4016) & 0x01, 1);
+        memory.write(0x4016, 0);
+        let bits: Vec<u8> = (0..10).map(|_| memory.read(0x4016) & 0x01).collect();
+        assert_eq!(bits, [1, 0, 0, 1, 1, 0, 0, 0, 1, 1]);
+        let bits: Vec<u8> = (0..3).map(|_| memory.read(0x4017) & 0x01).collect();
+        assert_eq!(bits, [1, 0, 0]);
+        // Peeking doesn't move on to the next button
+        assert_eq!(memory.peek(0x4017) & 0x01, 0);
+        assert_eq!(memory.peek(0x4017) & 0x01, 0);
+        // The top three bits are open bus
+        memory.write(0x0000, 0x40);
+        memory.read(0x0000);
+        assert_eq!(memory.read(0x4016) & 0xE0, 0x40);
+    }
+
+    #[test]
+    fn oam_dma() {
+        let mut memory = CpuMemory::new();
+        for low in 0..=0xFF {
+            memory.write(0x0300 | low, low as u8 ^ 0xFF);
+        }
+        memory.write(0x2003, 0x00);
+        memory.write(0x4014, 0x03);
+        memory.write(0x2003, 0x10);
+        assert_eq!(memory.read(0x2004), 0xEF);
+        assert!(memory.take_dma());
+        assert!(!memory.take_dma());
+    }
 }
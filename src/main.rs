@@ -1,5 +1,9 @@
+mod apu;
+mod cartridge;
+mod controller;
 mod cpu_memory;
 mod mos6502;
+mod ppu;
 use ggez::event::{self, EventHandler};
 use ggez::graphics::{self, Canvas, Color, DrawParam};
 use ggez::input::keyboard;
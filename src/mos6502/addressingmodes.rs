@@ -19,12 +19,13 @@
 * wrap when values are added to it.
 */
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum AddressingMode {
     Absolute,                 // Second and third byte form the address of the opperand.
     AbsoluteX, // Second and third bytes of instruction form an address to which the X register is added. The sum is the address of the operand
     AbsoluteY, // Second and third bytes of instruction form an address to which the Y register is added. The sum is the address of the operand
     AbsoluteIndirect, // Second and third bytes of instruction form an address to a pointer. Program Counter is set to this pointer
+    Accumulator,      // A single byte instruction operating on the accumulator
     Immediate,        // Operand is the second byte
     Implied,          // A single bye instruction, no operands
     Relative, // An offset in the second byte of the instruction is added to the program counter if the branch statement is true.
@@ -35,6 +36,26 @@ pub enum AddressingMode {
     ZeroPageIndirectIndexedY, // Retreives an address from the zero page. The Y address is then added to this address. The resulting address is a pointer to the operand.
 }
 
+impl AddressingMode {
+    // Number of bytes taken by the opcode and its operand
+    pub fn instruction_length(&self) -> u16 {
+        match self {
+            AddressingMode::Absolute
+            | AddressingMode::AbsoluteIndirect
+            | AddressingMode::AbsoluteX
+            | AddressingMode::AbsoluteY => 3,
+            AddressingMode::ZeroPage
+            | AddressingMode::ZeroPageX
+            | AddressingMode::ZeroPageY
+            | AddressingMode::ZeroPageIndirectIndexedY
+            | AddressingMode::ZeroPageIndexedIndirectX
+            | AddressingMode::Immediate
+            | AddressingMode::Relative => 2,
+            AddressingMode::Accumulator | AddressingMode::Implied => 1,
+        }
+    }
+}
+
 pub const ADDRESSING_MODES: [AddressingMode; 256] = {
     let mut res = [AddressingMode::Absolute; 256];
     // For loops not allowed in a const expression as of rustc 1.71.1
@@ -69,9 +90,23 @@ const fn group_one(opcode: u8) -> AddressingMode {
 
 const fn group_two(opcode: u8) -> AddressingMode {
     match (opcode & 0b00011100) >> 2 {
-        0b000 => AddressingMode::Immediate,
+        0b000 => {
+            // LDX and NOP #. The top half are JAM
+            if opcode < 0x80 {
+                AddressingMode::Implied
+            } else {
+                AddressingMode::Immediate
+            }
+        }
         0b001 => AddressingMode::ZeroPage,
-        0b010 => AddressingMode::Implied,
+        0b010 => {
+            // ASL, ROL, LSR and ROR A. The rest are TXA, TAX, DEX and NOP
+            if opcode < 0x80 {
+                AddressingMode::Accumulator
+            } else {
+                AddressingMode::Implied
+            }
+        }
         0b011 => AddressingMode::Absolute,
         0b100 => AddressingMode::Implied, // Every instruction here is JAM
         0b101 => {
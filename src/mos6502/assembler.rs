@@ -0,0 +1,609 @@
+/* A small two pass assembler, mostly for writing CPU tests without hand assembling hex.
+* Opcodes are found by looking the mnemonic and addressing mode up in the same tables the
+* CPU decodes with, so it knows every instruction the CPU does.
+*
+*         .org $8000
+* count = 8
+* start:  ldx #count - 1
+* loop:   lda table,x     ; zero page is used when the address is known to fit,
+*         sta $0200,x     ; unless it's written with more than two hex digits
+*         dex
+*         bpl loop
+*         jmp (vector)
+* table:  .byte 1, 2, 3, 4, $10 | 5, %1010, 'a', "bc"
+* vector: .word start, *+2
+*
+* Expressions can use labels, `*` for the current address, + - * / % & | ^ << >>,
+* - and ~ in front of a value, and < and > to take the low or high byte. Mnemonics are
+* case insensitive, a * in front of one picks its unofficial encoding where there's a
+* choice, so that disassembler output assembles back to the same instruction.
+*/
+
+use std::{collections::HashMap, error::Error, fmt};
+
+use super::{
+    addressingmodes::{AddressingMode, ADDRESSING_MODES},
+    instruction_table::{unofficial, Instruction, INSTRUCTIONS},
+};
+
+// Other names the unofficial instructions go by
+const ALIASES: [(&str, &str); 11] = [
+    ("ISB", "ISC"),
+    ("INS", "ISC"),
+    ("DCM", "DCP"),
+    ("ASR", "ALR"),
+    ("SBX", "AXS"),
+    ("SHA", "AHX"),
+    ("SHS", "TAS"),
+    ("ATX", "LXA"),
+    ("OAL", "LXA"),
+    ("JAM", "STP"),
+    ("KIL", "STP"),
+];
+
+#[derive(Debug)]
+pub struct AssemblyError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AssemblyError {}
+
+pub struct Assembly {
+    // Every .org starts a new run of bytes
+    pub segments: Vec<(u16, Vec<u8>)>,
+    pub labels: HashMap<String, u16>,
+}
+
+impl Assembly {
+    // Copies every segment into a 64K memory image
+    pub fn write_to(&self, memory: &mut [u8]) {
+        for (origin, bytes) in &self.segments {
+            for (offset, &byte) in bytes.iter().enumerate() {
+                memory[(*origin as usize + offset) & 0xFFFF] = byte;
+            }
+        }
+    }
+
+    // The bytes of all the segments one after the other
+    pub fn bytes(&self) -> Vec<u8> {
+        self.segments
+            .iter()
+            .flat_map(|(_, bytes)| bytes.iter().copied())
+            .collect()
+    }
+}
+
+enum Statement {
+    Org(u16),
+    Bytes(Vec<Data>),
+    Words(Vec<String>),
+    Instruction { opcode: u8, operand: Option<String> },
+}
+
+enum Data {
+    Expression(String),
+    Text(Vec<u8>),
+}
+
+// The shape of an operand, before its expression is worked out
+enum Syntax {
+    None,
+    Accumulator,
+    Immediate(String),
+    Plain(String),
+    IndexedX(String),
+    IndexedY(String),
+    Indirect(String),
+    IndexedIndirectX(String),
+    IndirectIndexedY(String),
+}
+
+pub fn assemble(source: &str) -> Result<Assembly, AssemblyError> {
+    let mut labels = HashMap::new();
+    let mut statements = Vec::new();
+    let mut address: u16 = 0;
+
+    // First pass, find out how long everything is and where the labels are
+    for (index, line) in source.lines().enumerate() {
+        let number = index + 1;
+        let error = |message: String| AssemblyError {
+            line: number,
+            message,
+        };
+        let mut text = strip_comment(line).trim();
+        if let Some((label, rest)) = split_label(text) {
+            if labels.insert(label.to_owned(), address).is_some() {
+                return Err(error(format!("{label} is defined twice")));
+            }
+            text = rest.trim();
+        }
+        if text.is_empty() {
+            continue;
+        }
+        if let Some((name, expression)) = split_constant(text) {
+            let value = evaluate(expression, &labels, address)
+                .and_then(|value| value.ok_or("uses a label that isn't defined yet".to_owned()))
+                .map_err(|message| error(format!("{name}: {message}")))?;
+            if labels.insert(name.to_owned(), value.value as u16).is_some() {
+                return Err(error(format!("{name} is defined twice")));
+            }
+            continue;
+        }
+        let (keyword, rest) = match text.find(char::is_whitespace) {
+            Some(split) => (&text[..split], text[split..].trim()),
+            None => (text, ""),
+        };
+        let statement = match keyword.to_ascii_lowercase().as_str() {
+            ".org" => {
+                let value = evaluate(rest, &labels, address)
+                    .and_then(|value| value.ok_or("uses a label that isn't defined yet".to_owned()))
+                    .map_err(|message| error(format!(".org {message}")))?;
+                address = value.value as u16;
+                Statement::Org(address)
+            }
+            ".byte" | ".db" => {
+                let data: Vec<Data> = split_list(rest)
+                    .into_iter()
+                    .map(|item| match item.strip_prefix('"') {
+                        Some(text) => Data::Text(text.trim_end_matches('"').bytes().collect()),
+                        None => Data::Expression(item.to_owned()),
+                    })
+                    .collect();
+                let length: usize = data
+                    .iter()
+                    .map(|item| match item {
+                        Data::Expression(_) => 1,
+                        Data::Text(text) => text.len(),
+                    })
+                    .sum();
+                address = address.wrapping_add(length as u16);
+                Statement::Bytes(data)
+            }
+            ".word" | ".dw" => {
+                let words: Vec<String> = split_list(rest).into_iter().map(str::to_owned).collect();
+                address = address.wrapping_add(2 * words.len() as u16);
+                Statement::Words(words)
+            }
+            _ => {
+                let (opcode, operand) = encode(keyword, rest, &labels, address).map_err(error)?;
+                address =
+                    address.wrapping_add(ADDRESSING_MODES[opcode as usize].instruction_length());
+                Statement::Instruction { opcode, operand }
+            }
+        };
+        statements.push((number, statement));
+    }
+
+    // Second pass, every label is known so the bytes can be filled in
+    let mut segments: Vec<(u16, Vec<u8>)> = Vec::new();
+    let mut address: u16 = 0;
+    for (number, statement) in statements {
+        let error = |message: String| AssemblyError {
+            line: number,
+            message,
+        };
+        let value = |expression: &str, address: u16| {
+            evaluate(expression, &labels, address)
+                .and_then(|value| value.ok_or("uses a label that isn't defined".to_owned()))
+                .map(|value| value.value)
+                .map_err(error)
+        };
+        let mut bytes = Vec::new();
+        match statement {
+            Statement::Org(origin) => {
+                segments.push((origin, Vec::new()));
+                address = origin;
+                continue;
+            }
+            Statement::Bytes(data) => {
+                for item in data {
+                    match item {
+                        Data::Expression(expression) => {
+                            bytes.push(byte(value(&expression, address)?).map_err(error)?)
+                        }
+                        Data::Text(text) => bytes.extend(text),
+                    }
+                }
+            }
+            Statement::Words(words) => {
+                for expression in words {
+                    let word = word(value(&expression, address)?).map_err(error)?;
+                    bytes.extend(word.to_le_bytes());
+                }
+            }
+            Statement::Instruction { opcode, operand } => {
+                bytes.push(opcode);
+                let mode = ADDRESSING_MODES[opcode as usize];
+                if let Some(expression) = operand {
+                    let value = value(&expression, address)?;
+                    match mode.instruction_length() {
+                        2 if mode == AddressingMode::Relative => {
+                            let offset = value - (address as i64 + 2);
+                            if !(-128..=127).contains(&offset) {
+                                return Err(error(format!("branch is {offset} bytes away")));
+                            }
+                            bytes.push(offset as u8);
+                        }
+                        2 => bytes.push(byte(value).map_err(error)?),
+                        _ => bytes.extend(word(value).map_err(error)?.to_le_bytes()),
+                    }
+                }
+            }
+        }
+        if segments.is_empty() {
+            segments.push((0, Vec::new()));
+        }
+        address = address.wrapping_add(bytes.len() as u16);
+        segments.last_mut().unwrap().1.extend(bytes);
+    }
+    Ok(Assembly { segments, labels })
+}
+
+// Picks the opcode for a mnemonic and operand. The operand's expression is handed back to
+// be worked out in the second pass.
+fn encode(
+    mnemonic: &str,
+    operand: &str,
+    labels: &HashMap<String, u16>,
+    address: u16,
+) -> Result<(u8, Option<String>), String> {
+    let (prefer_unofficial, mnemonic) = match mnemonic.strip_prefix('*') {
+        Some(mnemonic) => (true, mnemonic),
+        None => (false, mnemonic),
+    };
+    let mut name = mnemonic.to_ascii_uppercase();
+    if let Some((_, real)) = ALIASES.iter().find(|(alias, _)| *alias == name) {
+        name = real.to_string();
+    }
+    let instruction = INSTRUCTIONS
+        .iter()
+        .find(|instruction| format!("{:?}", instruction) == name)
+        .copied()
+        .ok_or_else(|| format!("unknown instruction {mnemonic}"))?;
+
+    // Zero page is only an option when the address is already known to fit
+    let zero_page = |expression: &str| match evaluate(expression, labels, address) {
+        Ok(Some(value)) => !value.wide && (0..=0xFF).contains(&value.value),
+        _ => false,
+    };
+    let (modes, expression) = match parse_syntax(operand, instruction) {
+        Syntax::None => (
+            vec![AddressingMode::Implied, AddressingMode::Accumulator],
+            None,
+        ),
+        Syntax::Accumulator => (vec![AddressingMode::Accumulator], None),
+        Syntax::Immediate(expression) => (vec![AddressingMode::Immediate], Some(expression)),
+        Syntax::Plain(expression) => {
+            let modes = if zero_page(&expression) {
+                vec![
+                    AddressingMode::Relative,
+                    AddressingMode::ZeroPage,
+                    AddressingMode::Absolute,
+                ]
+            } else {
+                vec![AddressingMode::Relative, AddressingMode::Absolute]
+            };
+            (modes, Some(expression))
+        }
+        Syntax::IndexedX(expression) => {
+            let modes = if zero_page(&expression) {
+                vec![AddressingMode::ZeroPageX, AddressingMode::AbsoluteX]
+            } else {
+                vec![AddressingMode::AbsoluteX]
+            };
+            (modes, Some(expression))
+        }
+        Syntax::IndexedY(expression) => {
+            let modes = if zero_page(&expression) {
+                vec![AddressingMode::ZeroPageY, AddressingMode::AbsoluteY]
+            } else {
+                vec![AddressingMode::AbsoluteY]
+            };
+            (modes, Some(expression))
+        }
+        Syntax::Indirect(expression) => (vec![AddressingMode::AbsoluteIndirect], Some(expression)),
+        Syntax::IndexedIndirectX(expression) => (
+            vec![AddressingMode::ZeroPageIndexedIndirectX],
+            Some(expression),
+        ),
+        Syntax::IndirectIndexedY(expression) => (
+            vec![AddressingMode::ZeroPageIndirectIndexedY],
+            Some(expression),
+        ),
+    };
+    let opcode = modes
+        .into_iter()
+        .find_map(|mode| find_opcode(instruction, mode, prefer_unofficial))
+        .ok_or_else(|| format!("{mnemonic} can't take the operand {operand:?}"))?;
+    Ok((opcode, expression))
+}
+
+// The tables read backwards. When several opcodes fit, an official one is picked unless
+// asked otherwise.
+fn find_opcode(
+    instruction: Instruction,
+    mode: AddressingMode,
+    prefer_unofficial: bool,
+) -> Option<u8> {
+    let matching: Vec<u8> = (0..=0xFF)
+        .filter(|&opcode| {
+            INSTRUCTIONS[opcode as usize] == instruction
+                && ADDRESSING_MODES[opcode as usize] == mode
+        })
+        .collect();
+    matching
+        .iter()
+        .find(|&&opcode| unofficial(opcode) == prefer_unofficial)
+        .or(matching.first())
+        .copied()
+}
+
+fn parse_syntax(operand: &str, instruction: Instruction) -> Syntax {
+    let operand: String = operand.chars().filter(|c| !c.is_whitespace()).collect();
+    let upper = operand.to_ascii_uppercase();
+    if operand.is_empty() {
+        Syntax::None
+    } else if upper == "A" {
+        Syntax::Accumulator
+    } else if let Some(expression) = operand.strip_prefix('#') {
+        Syntax::Immediate(expression.to_owned())
+    } else if operand.starts_with('(') && upper.ends_with(",X)") {
+        Syntax::IndexedIndirectX(operand[1..operand.len() - 3].to_owned())
+    } else if operand.starts_with('(') && upper.ends_with("),Y") {
+        Syntax::IndirectIndexedY(operand[1..operand.len() - 3].to_owned())
+    } else if operand.starts_with('(') && operand.ends_with(')') && instruction == Instruction::JMP
+    {
+        Syntax::Indirect(operand[1..operand.len() - 1].to_owned())
+    } else if upper.ends_with(",X") {
+        Syntax::IndexedX(operand[..operand.len() - 2].to_owned())
+    } else if upper.ends_with(",Y") {
+        Syntax::IndexedY(operand[..operand.len() - 2].to_owned())
+    } else {
+        Syntax::Plain(operand)
+    }
+}
+
+fn byte(value: i64) -> Result<u8, String> {
+    if (-0x80..=0xFF).contains(&value) {
+        Ok(value as u8)
+    } else {
+        Err(format!("{value} doesn't fit in a byte"))
+    }
+}
+
+fn word(value: i64) -> Result<u16, String> {
+    if (-0x8000..=0xFFFF).contains(&value) {
+        Ok(value as u16)
+    } else {
+        Err(format!("{value} doesn't fit in a word"))
+    }
+}
+
+// Drops everything after a ; that isn't inside quotes
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (index, c) in line.char_indices() {
+        match (quote, c) {
+            (None, ';') => return &line[..index],
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), _) if open == c => quote = None,
+            _ => (),
+        }
+    }
+    line
+}
+
+fn split_label(text: &str) -> Option<(&str, &str)> {
+    let (label, rest) = text.split_once(':')?;
+    is_identifier(label).then_some((label, rest))
+}
+
+fn split_constant(text: &str) -> Option<(&str, &str)> {
+    let (name, expression) = text.split_once('=')?;
+    let name = name.trim();
+    is_identifier(name).then_some((name, expression))
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Splits on the commas that aren't inside quotes
+fn split_list(text: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut quote = None;
+    let mut start = 0;
+    for (index, c) in text.char_indices() {
+        match (quote, c) {
+            (None, ',') => {
+                items.push(text[start..index].trim());
+                start = index + 1;
+            }
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), _) if open == c => quote = None,
+            _ => (),
+        }
+    }
+    items.push(text[start..].trim());
+    items.retain(|item| !item.is_empty());
+    items
+}
+
+struct Value {
+    value: i64,
+    // Written with more than two hex digits, so it's meant as an absolute address
+    wide: bool,
+}
+
+// Works out an expression. Ok(None) means it uses a label that isn't defined yet.
+fn evaluate(
+    text: &str,
+    labels: &HashMap<String, u16>,
+    address: u16,
+) -> Result<Option<Value>, String> {
+    let mut parser = Parser {
+        text: text.as_bytes(),
+        position: 0,
+        labels,
+        address,
+        wide: false,
+        undefined: false,
+    };
+    let value = parser.binary(0)?;
+    parser.skip_whitespace();
+    if parser.position != parser.text.len() {
+        return Err(format!("can't make sense of {text:?}"));
+    }
+    if parser.undefined {
+        return Ok(None);
+    }
+    Ok(Some(Value {
+        value,
+        wide: parser.wide,
+    }))
+}
+
+// Binary operators from the loosest to the tightest binding
+const OPERATORS: [&[&str]; 6] = [
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+struct Parser<'a> {
+    text: &'a [u8],
+    position: usize,
+    labels: &'a HashMap<String, u16>,
+    address: u16,
+    wide: bool,
+    undefined: bool,
+}
+
+impl Parser<'_> {
+    fn binary(&mut self, level: usize) -> Result<i64, String> {
+        if level == OPERATORS.len() {
+            return self.unary();
+        }
+        let mut value = self.binary(level + 1)?;
+        loop {
+            self.skip_whitespace();
+            let Some(&operator) = OPERATORS[level]
+                .iter()
+                .find(|operator| self.text[self.position..].starts_with(operator.as_bytes()))
+            else {
+                return Ok(value);
+            };
+            self.position += operator.len();
+            let right = self.binary(level + 1)?;
+            value = match operator {
+                "|" => value | right,
+                "^" => value ^ right,
+                "&" => value & right,
+                "<<" => value << (right & 63),
+                ">>" => value >> (right & 63),
+                "+" => value + right,
+                "-" => value - right,
+                "*" => value * right,
+                // Division by zero only happens with labels that aren't known yet
+                "/" => value.checked_div(right).unwrap_or(0),
+                _ => value.checked_rem(right).unwrap_or(0),
+            };
+        }
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        self.skip_whitespace();
+        let Some(&c) = self.text.get(self.position) else {
+            return Err("missing a value".to_owned());
+        };
+        match c {
+            b'-' | b'~' | b'<' | b'>' => {
+                self.position += 1;
+                let value = self.unary()?;
+                Ok(match c {
+                    b'-' => -value,
+                    b'~' => !value,
+                    b'<' => value & 0xFF,
+                    _ => (value >> 8) & 0xFF,
+                })
+            }
+            b'(' => {
+                self.position += 1;
+                let value = self.binary(0)?;
+                self.skip_whitespace();
+                if self.text.get(self.position) != Some(&b')') {
+                    return Err("missing a )".to_owned());
+                }
+                self.position += 1;
+                Ok(value)
+            }
+            b'*' => {
+                self.position += 1;
+                Ok(self.address as i64)
+            }
+            b'$' => {
+                self.position += 1;
+                let digits = self.take(|c| c.is_ascii_hexdigit());
+                if digits.len() > 2 {
+                    self.wide = true;
+                }
+                i64::from_str_radix(&digits, 16).map_err(|_| "bad hex number".to_owned())
+            }
+            b'%' => {
+                self.position += 1;
+                let digits = self.take(|c| c == b'0' || c == b'1');
+                i64::from_str_radix(&digits, 2).map_err(|_| "bad binary number".to_owned())
+            }
+            b'\'' => match self.text.get(self.position..self.position + 3) {
+                Some([b'\'', c, b'\'']) => {
+                    self.position += 3;
+                    Ok(*c as i64)
+                }
+                _ => Err("bad character".to_owned()),
+            },
+            b'0'..=b'9' => {
+                let digits = self.take(|c| c.is_ascii_digit());
+                digits.parse().map_err(|_| "bad number".to_owned())
+            }
+            _ => {
+                let name = self.take(|c| c.is_ascii_alphanumeric() || c == b'_');
+                if name.is_empty() {
+                    return Err(format!("unexpected {:?}", c as char));
+                }
+                match self.labels.get(&name) {
+                    Some(&value) => Ok(value as i64),
+                    None => {
+                        self.undefined = true;
+                        Ok(0)
+                    }
+                }
+            }
+        }
+    }
+
+    fn take(&mut self, accept: impl Fn(u8) -> bool) -> String {
+        let start = self.position;
+        while self.position < self.text.len() && accept(self.text[self.position]) {
+            self.position += 1;
+        }
+        String::from_utf8_lossy(&self.text[start..self.position]).into_owned()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.position < self.text.len() && self.text[self.position].is_ascii_whitespace() {
+            self.position += 1;
+        }
+    }
+}
@@ -0,0 +1,24 @@
+// Everything the CPU is connected to. CpuMemory is the NES implementation, but the CPU
+// can just as well run against a flat 64K memory or a mock that records the accesses.
+pub trait Bus {
+    fn read(&mut self, address: u16) -> u8;
+
+    fn write(&mut self, address: u16, value: u8);
+
+    // Reads without side effects, for debuggers and trace logs
+    fn peek(&self, address: u16) -> u8;
+
+    // Called once at the end of every CPU cycle
+    fn tick(&mut self) {}
+
+    // Whether something on the bus is pulling the IRQ line, like a mapper's IRQ counter
+    fn irq(&self) -> bool {
+        false
+    }
+
+    // Whether a write started a DMA since the last call. The CPU is halted for as long as the
+    // copy takes, see Mos6502::dma_cycles.
+    fn take_dma(&mut self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,119 @@
+/* Turns machine code back into assembly, using the same opcode tables as the CPU.
+* Operands are written the usual way for each addressing mode:
+*
+* LDA #$10    LDA $10    LDA $10,X    LDX $10,Y    LDA $1234    LDA $1234,X    LDA $1234,Y
+* JMP ($1234)    LDA ($10,X)    LDA ($10),Y    ASL A    BNE $C010
+*
+* Branch offsets are resolved to the address they jump to. Unofficial opcodes are shown
+* with a * in front of the mnemonic.
+*/
+
+use std::fmt;
+
+use super::{
+    addressingmodes::{AddressingMode, ADDRESSING_MODES},
+    bus::Bus,
+    instruction_table::{unofficial, Instruction, INSTRUCTIONS},
+};
+
+pub struct Disassembly {
+    pub address: u16,
+    pub instruction: Instruction,
+    pub mode: AddressingMode,
+    pub unofficial: bool,
+    // The opcode followed by its operand, as many bytes as the instruction is long
+    pub bytes: Vec<u8>,
+}
+
+impl Disassembly {
+    pub fn length(&self) -> u16 {
+        self.bytes.len() as u16
+    }
+
+    pub fn mnemonic(&self) -> String {
+        format!("{:?}", self.instruction)
+    }
+
+    pub fn operand(&self) -> String {
+        let byte = self.bytes.get(1).copied().unwrap_or(0);
+        let word = u16::from_be_bytes([self.bytes.get(2).copied().unwrap_or(0), byte]);
+        match self.mode {
+            AddressingMode::Implied => String::new(),
+            AddressingMode::Accumulator => "A".to_owned(),
+            AddressingMode::Immediate => format!("#${byte:02X}"),
+            AddressingMode::ZeroPage => format!("${byte:02X}"),
+            AddressingMode::ZeroPageX => format!("${byte:02X},X"),
+            AddressingMode::ZeroPageY => format!("${byte:02X},Y"),
+            AddressingMode::Absolute => format!("${word:04X}"),
+            AddressingMode::AbsoluteX => format!("${word:04X},X"),
+            AddressingMode::AbsoluteY => format!("${word:04X},Y"),
+            AddressingMode::AbsoluteIndirect => format!("(${word:04X})"),
+            AddressingMode::ZeroPageIndexedIndirectX => format!("(${byte:02X},X)"),
+            AddressingMode::ZeroPageIndirectIndexedY => format!("(${byte:02X}),Y"),
+            AddressingMode::Relative => format!("${:04X}", self.branch_target()),
+        }
+    }
+
+    // Where a branch goes if it's taken
+    pub fn branch_target(&self) -> u16 {
+        let offset = self.bytes.get(1).copied().unwrap_or(0) as i8;
+        self.address
+            .wrapping_add(self.length())
+            .wrapping_add(offset as u16)
+    }
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.unofficial {
+            write!(f, "*")?;
+        }
+        write!(f, "{}", self.mnemonic())?;
+        let operand = self.operand();
+        if !operand.is_empty() {
+            write!(f, " {operand}")?;
+        }
+        Ok(())
+    }
+}
+
+// Disassembles the instruction at `address` without disturbing anything on the bus
+pub fn disassemble<B: Bus>(bus: &B, address: u16) -> Disassembly {
+    decode(address, |i| bus.peek(address.wrapping_add(i)))
+}
+
+// Disassembles the instruction at the start of `bytes`, which are loaded at `address`.
+// Returns None if the instruction runs past the end of the slice.
+pub fn disassemble_bytes(bytes: &[u8], address: u16) -> Option<Disassembly> {
+    let opcode = *bytes.first()?;
+    let length = ADDRESSING_MODES[opcode as usize].instruction_length() as usize;
+    if bytes.len() < length {
+        return None;
+    }
+    Some(decode(address, |i| bytes[i as usize]))
+}
+
+// Disassembles every instruction in `bytes` in a row, stopping at one that's cut off
+pub fn disassemble_all(bytes: &[u8], address: u16) -> Vec<Disassembly> {
+    let mut disassembly = Vec::new();
+    let mut offset = 0;
+    while let Some(instruction) =
+        disassemble_bytes(&bytes[offset..], address.wrapping_add(offset as u16))
+    {
+        offset += instruction.bytes.len();
+        disassembly.push(instruction);
+    }
+    disassembly
+}
+
+fn decode(address: u16, byte: impl Fn(u16) -> u8) -> Disassembly {
+    let opcode = byte(0);
+    let mode = ADDRESSING_MODES[opcode as usize];
+    Disassembly {
+        address,
+        instruction: INSTRUCTIONS[opcode as usize],
+        mode,
+        unofficial: unofficial(opcode),
+        bytes: (0..mode.instruction_length()).map(byte).collect(),
+    }
+}
@@ -1,4 +1,4 @@
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Instruction {
     ADC,
     AHX,
@@ -44,6 +44,7 @@ pub enum Instruction {
     LDX,
     LDY,
     LSR,
+    LXA,
     NOP,
     ORA,
     PHA,
@@ -251,7 +252,7 @@ pub const INSTRUCTIONS: [Instruction; 256] = [
     Instruction::TAY,
     Instruction::LDA,
     Instruction::TAX,
-    Instruction::LAX,
+    Instruction::LXA,
     Instruction::LDY,
     Instruction::LDA,
     Instruction::LDX,
@@ -337,3 +338,35 @@ pub const INSTRUCTIONS: [Instruction; 256] = [
     Instruction::INC,
     Instruction::ISC,
 ];
+
+// Whether an opcode is outside the documented instruction set. Some of the unofficial
+// opcodes share a name with an official instruction, like the extra NOPs and SBC at $EB.
+pub fn unofficial(opcode: u8) -> bool {
+    match INSTRUCTIONS[opcode as usize] {
+        Instruction::NOP => opcode != 0xEA,
+        Instruction::SBC => opcode == 0xEB,
+        Instruction::AHX
+        | Instruction::ALR
+        | Instruction::ALS
+        | Instruction::ANC
+        | Instruction::ARR
+        | Instruction::AXS
+        | Instruction::DCD
+        | Instruction::DCP
+        | Instruction::ISC
+        | Instruction::LAS
+        | Instruction::LAX
+        | Instruction::LXA
+        | Instruction::RLA
+        | Instruction::RRA
+        | Instruction::SAX
+        | Instruction::SHX
+        | Instruction::SHY
+        | Instruction::SLO
+        | Instruction::SRE
+        | Instruction::STP
+        | Instruction::TAS
+        | Instruction::XAA => true,
+        _ => false,
+    }
+}
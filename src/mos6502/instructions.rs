@@ -1,41 +1,438 @@
-use std::ops::Add;
+use super::{bus::Bus, instruction_table::Instruction, Operand, Variant};
 
-use crate::cpu_memory::CpuMemory;
-
-use super::Operand;
+// Value ORed into the accumulator by the unstable XAA and LXA (LAX #imm) opcodes
+const UNSTABLE_MAGIC: u8 = 0xEE;
 
+// The instructions are split by how they use their operand so that both the
+// whole-instruction and the cycle stepped cores can share them. The cores are
+// responsible for the bus accesses, the functions here only see values.
 impl super::Mos6502 {
-    pub fn adc(&mut self, memory: &mut CpuMemory, operand: Operand) {
-        let operand = operand.read(memory);
-        if !self.decimal_mode {
-            let res = operand.overflowing_add(self.accumulator);
-            let carry_in_res = res.0.overflowing_add(self.carry as u8);
-            self.carry = res.1 | carry_in_res.1;
-            self.accumulator = carry_in_res.0;
+    // Instructions that only read their operand
+    pub fn execute_read(&mut self, instruction: Instruction, value: u8) {
+        match instruction {
+            Instruction::ADC => self.adc(value),
+            Instruction::AND => self.and(value),
+            Instruction::BIT => self.bit(value),
+            Instruction::CMP => self.compare(self.accumulator, value),
+            Instruction::CPX => self.compare(self.index_x, value),
+            Instruction::CPY => self.compare(self.index_y, value),
+            Instruction::EOR => self.eor(value),
+            Instruction::LDA => self.lda(value),
+            Instruction::LDX => self.ldx(value),
+            Instruction::LDY => self.ldy(value),
+            Instruction::ORA => self.ora(value),
+            Instruction::SBC => self.sbc(value),
+            Instruction::LAX => self.lax(value),
+            Instruction::ANC => self.anc(value),
+            Instruction::ALR => self.alr(value),
+            Instruction::ARR => self.arr(value),
+            Instruction::AXS => self.axs(value),
+            Instruction::LAS => self.las(value),
+            Instruction::XAA => self.xaa(value),
+            Instruction::LXA => self.lxa(value),
+            // The multi-byte NOPs still perform the read
+            Instruction::NOP => (),
+            _ => panic!("Not a read instruction"),
+        }
+    }
+
+    // Read-modify-write instructions, returns the value written back
+    pub fn execute_modify(&mut self, instruction: Instruction, value: u8) -> u8 {
+        match instruction {
+            Instruction::ASL => self.asl(value),
+            Instruction::LSR => self.lsr(value),
+            Instruction::ROL => self.rol(value),
+            Instruction::ROR => self.ror(value),
+            Instruction::INC => self.inc(value),
+            Instruction::DEC => self.dec(value),
+            Instruction::SLO => self.slo(value),
+            Instruction::RLA => self.rla(value),
+            Instruction::SRE => self.sre(value),
+            Instruction::RRA => self.rra(value),
+            Instruction::DCP => self.dcp(value),
+            Instruction::ISC => self.isc(value),
+            _ => panic!("Not a read-modify-write instruction"),
+        }
+    }
+
+    // Store instructions, returns the address and value that actually get written
+    pub fn execute_store(&mut self, instruction: Instruction, address: u16) -> (u16, u8) {
+        match instruction {
+            Instruction::STA => (address, self.accumulator),
+            Instruction::STX => (address, self.index_x),
+            Instruction::STY => (address, self.index_y),
+            Instruction::SAX => (address, self.accumulator & self.index_x),
+            Instruction::SHX => self.store_high_and(address, self.index_y, self.index_x),
+            Instruction::SHY => self.store_high_and(address, self.index_x, self.index_y),
+            Instruction::AHX => {
+                self.store_high_and(address, self.index_y, self.accumulator & self.index_x)
+            }
+            Instruction::TAS => {
+                self.stack_pointer = self.accumulator & self.index_x;
+                self.store_high_and(address, self.index_y, self.stack_pointer)
+            }
+            _ => panic!("Not a store instruction"),
+        }
+    }
+
+    // Single byte instructions that only touch registers
+    pub fn execute_implied(&mut self, instruction: Instruction) {
+        match instruction {
+            Instruction::CLC => self.carry = false,
+            Instruction::SEC => self.carry = true,
+            Instruction::CLI => self.interrupt_enable = true,
+            Instruction::SEI => self.interrupt_enable = false,
+            Instruction::CLD => self.decimal_mode = false,
+            Instruction::SED => self.decimal_mode = true,
+            Instruction::CLV => self.overflow = false,
+            Instruction::DEX => self.index_x = self.set_sign_and_zero(self.index_x.wrapping_sub(1)),
+            Instruction::DEY => self.index_y = self.set_sign_and_zero(self.index_y.wrapping_sub(1)),
+            Instruction::INX => self.index_x = self.set_sign_and_zero(self.index_x.wrapping_add(1)),
+            Instruction::INY => self.index_y = self.set_sign_and_zero(self.index_y.wrapping_add(1)),
+            Instruction::TAX => self.index_x = self.set_sign_and_zero(self.accumulator),
+            Instruction::TAY => self.index_y = self.set_sign_and_zero(self.accumulator),
+            Instruction::TXA => self.accumulator = self.set_sign_and_zero(self.index_x),
+            Instruction::TYA => self.accumulator = self.set_sign_and_zero(self.index_y),
+            Instruction::TSX => self.index_x = self.set_sign_and_zero(self.stack_pointer),
+            // TXS is the only transfer that leaves the flags alone
+            Instruction::TXS => self.stack_pointer = self.index_x,
+            Instruction::NOP => (),
+            _ => panic!("Not an implied instruction"),
+        }
+    }
+
+    pub fn adc(&mut self, operand: u8) {
+        let binary = self.accumulator as u16 + operand as u16 + self.carry as u16;
+        self.zero = binary as u8 == 0;
+        if !self.decimal_arithmetic() {
+            self.overflow =
+                (self.accumulator ^ binary as u8) & (operand ^ binary as u8) & 0x80 != 0;
+            self.carry = binary > 0xFF;
+            self.accumulator = binary as u8;
+            self.sign = self.accumulator & 0x80 != 0;
+        } else {
+            // NMOS decimal mode: N and V come from the intermediate result after the low
+            // nibble has been adjusted, Z from the plain binary sum
+            let mut low =
+                (self.accumulator & 0x0F) as u16 + (operand & 0x0F) as u16 + self.carry as u16;
+            if low > 9 {
+                low += 6;
+            }
+            let mut high =
+                (self.accumulator >> 4) as u16 + (operand >> 4) as u16 + (low > 0x0F) as u16;
+            let intermediate = (high << 4) as u8;
+            self.sign = intermediate & 0x80 != 0;
+            self.overflow =
+                (self.accumulator ^ intermediate) & !(self.accumulator ^ operand) & 0x80 != 0;
+            if high > 9 {
+                high += 6;
+            }
+            self.carry = high > 0x0F;
+            self.accumulator = ((high << 4) as u8) | (low as u8 & 0x0F);
+        }
+    }
+
+    pub fn sbc(&mut self, operand: u8) {
+        let borrow = !self.carry as u16;
+        let binary = (self.accumulator as u16)
+            .wrapping_sub(operand as u16)
+            .wrapping_sub(borrow);
+        // Flags are always taken from the binary result, even in decimal mode
+        self.overflow =
+            (self.accumulator ^ operand) & (self.accumulator ^ binary as u8) & 0x80 != 0;
+        self.carry = binary < 0x100;
+        self.set_sign_and_zero(binary as u8);
+        if !self.decimal_arithmetic() {
+            self.accumulator = binary as u8;
+        } else {
+            let mut low =
+                (self.accumulator & 0x0F) as i16 - (operand & 0x0F) as i16 - borrow as i16;
+            let mut high = (self.accumulator >> 4) as i16 - (operand >> 4) as i16;
+            if low < 0 {
+                low -= 6;
+                high -= 1;
+            }
+            if high < 0 {
+                high -= 6;
+            }
+            self.accumulator = ((high << 4) as u8) | (low as u8 & 0x0F);
+        }
+    }
+
+    pub fn and(&mut self, operand: u8) {
+        self.accumulator = self.set_sign_and_zero(self.accumulator & operand);
+    }
+
+    pub fn ora(&mut self, operand: u8) {
+        self.accumulator = self.set_sign_and_zero(self.accumulator | operand);
+    }
+
+    pub fn eor(&mut self, operand: u8) {
+        self.accumulator = self.set_sign_and_zero(self.accumulator ^ operand);
+    }
+
+    pub fn bit(&mut self, operand: u8) {
+        self.zero = self.accumulator & operand == 0;
+        self.overflow = operand & 0b01000000 != 0;
+        self.sign = operand & 0b10000000 != 0;
+    }
+
+    pub fn lda(&mut self, operand: u8) {
+        self.accumulator = self.set_sign_and_zero(operand);
+    }
+
+    pub fn ldx(&mut self, operand: u8) {
+        self.index_x = self.set_sign_and_zero(operand);
+    }
+
+    pub fn ldy(&mut self, operand: u8) {
+        self.index_y = self.set_sign_and_zero(operand);
+    }
+
+    pub fn asl(&mut self, value: u8) -> u8 {
+        self.carry = value & 0b10000000 != 0;
+        self.set_sign_and_zero(value << 1)
+    }
+
+    pub fn lsr(&mut self, value: u8) -> u8 {
+        self.carry = value & 0b00000001 != 0;
+        self.set_sign_and_zero(value >> 1)
+    }
+
+    pub fn rol(&mut self, value: u8) -> u8 {
+        let carry_in = self.carry as u8;
+        self.carry = value & 0b10000000 != 0;
+        self.set_sign_and_zero((value << 1) | carry_in)
+    }
+
+    pub fn ror(&mut self, value: u8) -> u8 {
+        let carry_in = (self.carry as u8) << 7;
+        self.carry = value & 0b00000001 != 0;
+        self.set_sign_and_zero((value >> 1) | carry_in)
+    }
+
+    pub fn inc(&mut self, value: u8) -> u8 {
+        self.set_sign_and_zero(value.wrapping_add(1))
+    }
+
+    pub fn dec(&mut self, value: u8) -> u8 {
+        self.set_sign_and_zero(value.wrapping_sub(1))
+    }
+
+    // Unofficial instructions. Most of them are a combination of two official instructions
+    // sharing a single opcode, see https://www.nesdev.org/wiki/Programming_with_unofficial_opcodes
+
+    pub fn slo(&mut self, value: u8) -> u8 {
+        let value = self.asl(value);
+        self.ora(value);
+        value
+    }
+
+    pub fn rla(&mut self, value: u8) -> u8 {
+        let value = self.rol(value);
+        self.and(value);
+        value
+    }
+
+    pub fn sre(&mut self, value: u8) -> u8 {
+        let value = self.lsr(value);
+        self.eor(value);
+        value
+    }
+
+    pub fn rra(&mut self, value: u8) -> u8 {
+        let value = self.ror(value);
+        self.adc(value);
+        value
+    }
+
+    pub fn dcp(&mut self, value: u8) -> u8 {
+        let value = value.wrapping_sub(1);
+        self.compare(self.accumulator, value);
+        value
+    }
+
+    pub fn isc(&mut self, value: u8) -> u8 {
+        let value = value.wrapping_add(1);
+        self.sbc(value);
+        value
+    }
+
+    pub fn lax(&mut self, operand: u8) {
+        self.accumulator = self.set_sign_and_zero(operand);
+        self.index_x = operand;
+    }
+
+    pub fn anc(&mut self, operand: u8) {
+        self.and(operand);
+        self.carry = self.sign;
+    }
+
+    pub fn alr(&mut self, operand: u8) {
+        self.accumulator = self.lsr(self.accumulator & operand);
+    }
+
+    pub fn arr(&mut self, operand: u8) {
+        let value = self.accumulator & operand;
+        self.accumulator = self.set_sign_and_zero((value >> 1) | ((self.carry as u8) << 7));
+        if !self.decimal_arithmetic() {
+            self.carry = self.accumulator & 0b01000000 != 0;
+            self.overflow = ((self.accumulator >> 6) ^ (self.accumulator >> 5)) & 1 != 0;
+        } else {
+            // Decimal mode fixes up each nibble of the rotated value like ADC would
+            self.overflow = (value ^ self.accumulator) & 0b01000000 != 0;
+            let low = value & 0x0F;
+            let high = value >> 4;
+            if low + (low & 1) > 5 {
+                self.accumulator =
+                    (self.accumulator & 0xF0) | (self.accumulator.wrapping_add(6) & 0x0F);
+            }
+            self.carry = high + (high & 1) > 5;
+            if self.carry {
+                self.accumulator = self.accumulator.wrapping_add(0x60);
+            }
+        }
+    }
+
+    pub fn axs(&mut self, operand: u8) {
+        let register = self.accumulator & self.index_x;
+        self.compare(register, operand);
+        self.index_x = register.wrapping_sub(operand);
+    }
+
+    pub fn las(&mut self, operand: u8) {
+        let value = self.set_sign_and_zero(operand & self.stack_pointer);
+        self.accumulator = value;
+        self.index_x = value;
+        self.stack_pointer = value;
+    }
+
+    pub fn xaa(&mut self, operand: u8) {
+        let value = (self.accumulator | UNSTABLE_MAGIC) & self.index_x & operand;
+        self.accumulator = self.set_sign_and_zero(value);
+    }
+
+    pub fn lxa(&mut self, operand: u8) {
+        let value = (self.accumulator | UNSTABLE_MAGIC) & operand;
+        self.lax(value);
+    }
+
+    // Control flow and stack instructions, used by the whole-instruction core. The cycle
+    // stepped core spreads these over several cycles itself.
+
+    // Returns the extra cycles taken, one for a taken branch and another one if the
+    // destination is on a different page
+    pub fn branch(&mut self, condition: bool, operand: Operand) -> usize {
+        if let Operand::Offset(offset) = operand {
+            if !condition {
+                return 0;
+            }
+            let prev_counter = self.program_counter;
+            self.program_counter = self.program_counter.wrapping_add(offset as u16);
+            if self.program_counter >> 8 != prev_counter >> 8 {
+                2
+            } else {
+                1
+            }
         } else {
-            let a_low = self.accumulator & 0b00001111;
-            let a_high = (self.accumulator & 0b11110000) >> 4;
-            let op_low = operand & 0b00001111;
-            let op_high = (operand & 0b11110000) >> 4;
+            panic!("Branch instructions require a relative operand");
+        }
+    }
+
+    pub fn jmp(&mut self, operand: Operand) {
+        if let Operand::Address(address) = operand {
+            self.program_counter = address;
+        } else {
+            panic!("JMP requires an address operand");
+        }
+    }
+
+    pub fn jsr<B: Bus>(&mut self, bus: &mut B, operand: Operand) {
+        if let Operand::Address(address) = operand {
+            // The pushed return address points at the last byte of the JSR
+            let [high, low] = self.program_counter.wrapping_sub(1).to_be_bytes();
+            self.push(bus, high);
+            self.push(bus, low);
+            self.program_counter = address;
+        } else {
+            panic!("JSR requires an address operand");
+        }
+    }
+
+    pub fn rts<B: Bus>(&mut self, bus: &mut B) {
+        let low = self.pull(bus);
+        let high = self.pull(bus);
+        self.program_counter = u16::from_be_bytes([high, low]).wrapping_add(1);
+    }
 
-            let res_lower = a_low + op_low + self.carry as u8;
-            let carry = res_lower > 9;
-            let res_lower = res_lower % 10;
+    pub fn rti<B: Bus>(&mut self, bus: &mut B) {
+        let status = self.pull(bus);
+        self.set_status(status);
+        let low = self.pull(bus);
+        let high = self.pull(bus);
+        self.program_counter = u16::from_be_bytes([high, low]);
+    }
+
+    pub fn brk<B: Bus>(&mut self, bus: &mut B) {
+        // BRK is followed by a padding byte that is skipped on return
+        let [high, low] = self.program_counter.wrapping_add(1).to_be_bytes();
+        self.push(bus, high);
+        self.push(bus, low);
+        self.push(bus, self.status(true));
+        self.poll_nmi();
+        let vector = self.interrupt_vector();
+        self.interrupt_enable = false;
+        let low = bus.read(vector);
+        let high = bus.read(vector + 1);
+        self.program_counter = u16::from_be_bytes([high, low]);
+    }
 
-            let res_upper = a_high + op_high + carry as u8;
-            self.carry = res_upper > 9;
-            let res_upper = res_upper % 10;
+    pub fn pha<B: Bus>(&mut self, bus: &mut B) {
+        self.push(bus, self.accumulator);
+    }
 
-            self.accumulator = (res_upper << 4) | res_lower;
+    pub fn php<B: Bus>(&mut self, bus: &mut B) {
+        self.push(bus, self.status(true));
+    }
+
+    pub fn pla<B: Bus>(&mut self, bus: &mut B) {
+        let value = self.pull(bus);
+        self.accumulator = self.set_sign_and_zero(value);
+    }
+
+    pub fn plp<B: Bus>(&mut self, bus: &mut B) {
+        let status = self.pull(bus);
+        self.set_status(status);
+    }
+
+    fn compare(&mut self, register: u8, operand: u8) {
+        self.carry = register >= operand;
+        self.set_sign_and_zero(register.wrapping_sub(operand));
+    }
+
+    // SHX, SHY, AHX and TAS store `value` ANDed with the high byte of the base address plus
+    // one. When the indexing crosses a page the stored value also replaces the high byte of
+    // the target address.
+    fn store_high_and(&self, address: u16, index: u8, value: u8) -> (u16, u8) {
+        let base = address.wrapping_sub(index as u16);
+        let [high, low] = address.to_be_bytes();
+        let value = value & ((base >> 8) as u8).wrapping_add(1);
+        if base & 0xFF00 != address & 0xFF00 {
+            (u16::from_be_bytes([value, low]), value)
+        } else {
+            (u16::from_be_bytes([high, low]), value)
         }
     }
 
-    pub fn and(&mut self, memory: &mut CpuMemory, operand: Operand) {
-        let operand = operand.read(memory);
-        self.accumulator = self.accumulator & operand;
+    // The 2A03 keeps the D flag but its ALU has the BCD adjustment cut out
+    fn decimal_arithmetic(&self) -> bool {
+        self.decimal_mode && self.variant == Variant::Nmos6502
     }
 
-    pub fn asl(&mut self) {
-        self.accumulator <<= 1;
+    // Returns the value to allow chaining it into a register
+    fn set_sign_and_zero(&mut self, value: u8) -> u8 {
+        self.zero = value == 0;
+        self.sign = value & 0b10000000 != 0;
+        value
     }
 }
@@ -0,0 +1,138 @@
+/* https://www.nesdev.org/wiki/CPU_interrupts
+* NMI is edge triggered: the line going from high to low latches a pending NMI that stays
+* pending until it is serviced. IRQ is level triggered and ignored while the I flag is set.
+*
+* Interrupts are polled at the end of the second to last cycle of every instruction. CLI,
+* SEI and PLP change the I flag in their last cycle, so an IRQ is only let through (or
+* still let through) after the instruction that follows them.
+*
+* An NMI that arrives while a BRK or IRQ is pushing the status register hijacks it, the
+* NMI vector is used instead but the pushed B flag is that of the original interrupt.
+*/
+
+use super::{bus::Bus, instruction_table::Instruction};
+
+pub const NMI_VECTOR: u16 = 0xFFFA;
+pub const RESET_VECTOR: u16 = 0xFFFC;
+pub const IRQ_VECTOR: u16 = 0xFFFE;
+
+// What the BRK style sequence that's currently executing is for
+#[derive(Clone, Copy, PartialEq)]
+pub enum InterruptKind {
+    Software,
+    Hardware,
+    Reset,
+}
+
+impl super::Mos6502 {
+    // Schedules the reset sequence, it runs instead of the next instruction
+    pub fn reset(&mut self) {
+        self.interrupt_kind = InterruptKind::Reset;
+        self.cycle = 0;
+        self.halted = false;
+    }
+
+    pub fn set_nmi_line(&mut self, asserted: bool) {
+        self.nmi_line = asserted;
+    }
+
+    pub fn set_irq_line(&mut self, asserted: bool) {
+        self.irq_line = asserted;
+    }
+
+    fn irq_asserted(&self) -> bool {
+        self.irq_line || self.bus_irq
+    }
+
+    fn interrupt_requested(&self) -> bool {
+        self.prev_need_nmi || self.prev_run_irq
+    }
+
+    // Samples the interrupt lines at the end of a cycle
+    pub fn poll_interrupts(&mut self) {
+        self.prev_need_nmi = self.need_nmi;
+        self.poll_nmi();
+        self.prev_run_irq = self.run_irq;
+        self.run_irq = self.irq_asserted() && self.interrupt_enable;
+    }
+
+    // The whole-instruction core only samples the lines once per instruction, this fills
+    // both stages of the polling pipeline as if the last two cycles had been polled
+    pub fn poll_interrupts_after(&mut self, instruction: Instruction, interrupt_enable: bool) {
+        self.poll_nmi();
+        self.prev_need_nmi = self.need_nmi;
+        let interrupt_enable = match instruction {
+            Instruction::CLI | Instruction::SEI | Instruction::PLP => interrupt_enable,
+            _ => self.interrupt_enable,
+        };
+        self.prev_run_irq = self.irq_asserted() && interrupt_enable;
+        self.run_irq = self.irq_asserted() && self.interrupt_enable;
+    }
+
+    // Latches an NMI edge. The cycle stepped core polls during the pushes of a BRK or an
+    // interrupt sequence, so the whole-instruction core does it before picking the vector
+    // too, letting an NMI that came in before the sequence hijack it.
+    pub fn poll_nmi(&mut self) {
+        if self.nmi_line && !self.nmi_previous {
+            self.need_nmi = true;
+        }
+        self.nmi_previous = self.nmi_line;
+    }
+
+    // Decides where an interrupt sequence jumps to, called when the status is pushed
+    pub fn interrupt_vector(&mut self) -> u16 {
+        if self.interrupt_kind == InterruptKind::Reset {
+            RESET_VECTOR
+        } else if self.need_nmi {
+            self.need_nmi = false;
+            NMI_VECTOR
+        } else {
+            IRQ_VECTOR
+        }
+    }
+
+    // Runs a pending reset, NMI or IRQ in one go for the whole-instruction core.
+    // Returns the number of cycles taken, or None if nothing was pending.
+    pub fn run_interrupt<B: Bus>(&mut self, bus: &mut B) -> Option<usize> {
+        if self.interrupt_kind == InterruptKind::Reset {
+            // The reset sequence goes through the motions of pushing without writing
+            for _ in 0..3 {
+                bus.read(0x0100 | self.stack_pointer as u16);
+                self.stack_pointer = self.stack_pointer.wrapping_sub(1);
+            }
+        } else if self.interrupt_requested() {
+            self.interrupt_kind = InterruptKind::Hardware;
+            let [high, low] = self.program_counter.to_be_bytes();
+            self.push(bus, high);
+            self.push(bus, low);
+            self.push(bus, self.status(false));
+            self.poll_nmi();
+        } else {
+            return None;
+        }
+        let vector = self.interrupt_vector();
+        self.interrupt_enable = false;
+        let low = bus.read(vector);
+        let high = bus.read(vector + 1);
+        self.program_counter = u16::from_be_bytes([high, low]);
+        self.interrupt_kind = InterruptKind::Software;
+        self.prev_need_nmi = false;
+        self.prev_run_irq = false;
+        Some(7)
+    }
+
+    // Called by the cycle stepped core instead of fetching an opcode. Returns true if
+    // an interrupt sequence has been started.
+    pub fn start_interrupt<B: Bus>(&mut self, bus: &mut B) -> bool {
+        if self.interrupt_kind != InterruptKind::Reset && !self.interrupt_requested() {
+            return false;
+        }
+        if self.interrupt_kind == InterruptKind::Software {
+            self.interrupt_kind = InterruptKind::Hardware;
+        }
+        // The opcode fetch still happens but is thrown away, and the PC isn't incremented
+        bus.read(self.program_counter);
+        self.opcode = 0x00;
+        true
+    }
+}
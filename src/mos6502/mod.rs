@@ -1,16 +1,34 @@
 mod addressingmodes;
+mod assembler;
+mod bus;
+mod disassembler;
 mod instruction_table;
 mod instructions;
+mod interrupts;
+mod status;
+#[cfg(test)]
+mod tests;
+mod tick;
 mod timing;
+mod trace;
 
 use core::panic;
 
 use addressingmodes::{AddressingMode, ADDRESSING_MODES};
 use instruction_table::{Instruction, INSTRUCTIONS};
+use interrupts::InterruptKind;
 
-use crate::cpu_memory::CpuMemory;
+pub use bus::Bus;
+
+// The NES uses a Ricoh 2A03, an NMOS 6502 without decimal mode
+#[derive(Clone, Copy, PartialEq)]
+pub enum Variant {
+    Ricoh2A03,
+    Nmos6502,
+}
 
 struct Mos6502 {
+    variant: Variant,
     program_counter: u16,
     accumulator: u8,
     index_x: u8,
@@ -20,127 +38,273 @@ struct Mos6502 {
     zero: bool,
     interrupt_enable: bool,
     decimal_mode: bool,
-    interrupt: bool,
     overflow: bool,
     sign: bool,
+    // Set by the STP/JAM opcodes, only a reset gets the CPU going again
+    halted: bool,
+    // Instruction in flight in the cycle stepped core. `cycle` is 0 between instructions.
+    opcode: u8,
+    cycle: u8,
+    address: u16,
+    pointer: u8,
+    data: u8,
+    page_crossed: bool,
+    // Interrupt lines as driven by the rest of the system and the polling pipeline
+    nmi_line: bool,
+    nmi_previous: bool,
+    need_nmi: bool,
+    prev_need_nmi: bool,
+    irq_line: bool,
+    // The IRQ line as pulled by the devices on the bus, see Bus::irq
+    bus_irq: bool,
+    run_irq: bool,
+    prev_run_irq: bool,
+    interrupt_kind: InterruptKind,
+    // Cycles run since power on
+    total_cycles: u64,
+    // Cycles the cycle stepped core still has to sit out for DMA
+    dma_stall: u64,
 }
 
 impl Mos6502 {
+    // Power-on state, the reset sequence runs first and loads the PC from the reset vector
+    pub fn new(variant: Variant) -> Self {
+        Mos6502 {
+            variant,
+            program_counter: 0,
+            accumulator: 0,
+            index_x: 0,
+            index_y: 0,
+            stack_pointer: 0,
+            carry: false,
+            zero: false,
+            interrupt_enable: false,
+            decimal_mode: false,
+            overflow: false,
+            sign: false,
+            halted: false,
+            opcode: 0,
+            cycle: 0,
+            address: 0,
+            pointer: 0,
+            data: 0,
+            page_crossed: false,
+            nmi_line: false,
+            nmi_previous: false,
+            need_nmi: false,
+            prev_need_nmi: false,
+            irq_line: false,
+            bus_irq: false,
+            run_irq: false,
+            prev_run_irq: false,
+            interrupt_kind: InterruptKind::Reset,
+            total_cycles: 0,
+            dma_stall: 0,
+        }
+    }
+
     // Returns the number of cycles this instruction took
-    fn run_instruction(&mut self, memory: &mut CpuMemory) -> usize {
-        let opcode = memory.read(self.program_counter);
+    fn run_instruction<B: Bus>(&mut self, bus: &mut B) -> usize {
+        // Finish off an instruction started with tick
+        if self.cycle != 0 || self.dma_stall != 0 {
+            let mut cycles = 1;
+            while !self.tick(bus) {
+                cycles += 1;
+            }
+            return cycles;
+        }
+        let mut cycles = self.execute_instruction(bus);
+        self.total_cycles += cycles as u64;
+        if bus.take_dma() {
+            let stall = self.dma_cycles();
+            self.total_cycles += stall;
+            cycles += stall as usize;
+        }
+        for _ in 0..cycles {
+            bus.tick();
+        }
+        cycles
+    }
+
+    // OAM DMA halts the CPU for 513 cycles after the write that started it, and one more to
+    // line up with the APU's get and put cycles when that write was on an odd cycle.
+    // https://www.nesdev.org/wiki/DMA#OAM_DMA
+    fn dma_cycles(&self) -> u64 {
+        if (self.total_cycles - 1) % 2 == 1 {
+            514
+        } else {
+            513
+        }
+    }
+
+    fn execute_instruction<B: Bus>(&mut self, bus: &mut B) -> usize {
+        // A jammed CPU still takes cycles, one at a time like the cycle stepped core
+        if self.halted {
+            bus.read(0xFFFF);
+            return 1;
+        }
+        if let Some(cycles) = self.run_interrupt(bus) {
+            return cycles;
+        }
+        let opcode = bus.read(self.program_counter);
         let addressing_mode: addressingmodes::AddressingMode = ADDRESSING_MODES[opcode as usize];
         let instruction = INSTRUCTIONS[opcode as usize];
-        let (operand, crossed_page) = self.get_operand(memory, addressing_mode);
+        let (operand, crossed_page) = self.get_operand(bus, addressing_mode);
+        self.move_program_counter(addressing_mode);
+        let mut cycles = timing::get_timing(addressing_mode, instruction, crossed_page);
+        let interrupt_enable = self.interrupt_enable;
         match instruction {
-            Instruction::ADC => self.adc(memory, operand),
-            _ => todo!(),
+            Instruction::BCC => cycles += self.branch(!self.carry, operand),
+            Instruction::BCS => cycles += self.branch(self.carry, operand),
+            Instruction::BEQ => cycles += self.branch(self.zero, operand),
+            Instruction::BMI => cycles += self.branch(self.sign, operand),
+            Instruction::BNE => cycles += self.branch(!self.zero, operand),
+            Instruction::BPL => cycles += self.branch(!self.sign, operand),
+            Instruction::BVC => cycles += self.branch(!self.overflow, operand),
+            Instruction::BVS => cycles += self.branch(self.overflow, operand),
+            Instruction::BRK => self.brk(bus),
+            Instruction::JMP => self.jmp(operand),
+            Instruction::JSR => self.jsr(bus, operand),
+            Instruction::RTI => self.rti(bus),
+            Instruction::RTS => self.rts(bus),
+            Instruction::PHA => self.pha(bus),
+            Instruction::PHP => self.php(bus),
+            Instruction::PLA => self.pla(bus),
+            Instruction::PLP => self.plp(bus),
+            Instruction::STP => self.halted = true,
+            _ => match operand {
+                Operand::Implied => self.execute_implied(instruction),
+                Operand::Accumulator => {
+                    self.accumulator = self.execute_modify(instruction, self.accumulator)
+                }
+                Operand::Immediate(value) => self.execute_read(instruction, value),
+                Operand::Address(address) => {
+                    if instruction.rwr() {
+                        // The unmodified value is written back first, which mappers can see
+                        let value = bus.read(address);
+                        bus.write(address, value);
+                        let value = self.execute_modify(instruction, value);
+                        bus.write(address, value);
+                    } else if instruction.store() {
+                        let (address, value) = self.execute_store(instruction, address);
+                        bus.write(address, value);
+                    } else {
+                        let value = bus.read(address);
+                        self.execute_read(instruction, value);
+                    }
+                }
+                Operand::Offset(_) => panic!("Relative operand on a non-branch instruction"),
+            },
         }
-        0
+        self.bus_irq = bus.irq();
+        self.poll_interrupts_after(instruction, interrupt_enable);
+        cycles
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.total_cycles
+    }
+
+    fn push<B: Bus>(&mut self, bus: &mut B, value: u8) {
+        bus.write(0x0100 | self.stack_pointer as u16, value);
+        self.stack_pointer = self.stack_pointer.wrapping_sub(1);
     }
 
-    fn get_operand(&self, memory: &CpuMemory, mode: AddressingMode) -> (Operand, bool) {
+    fn pull<B: Bus>(&mut self, bus: &mut B) -> u8 {
+        self.stack_pointer = self.stack_pointer.wrapping_add(1);
+        bus.read(0x0100 | self.stack_pointer as u16)
+    }
+
+    // Resolves the operand of the instruction at the program counter, and whether indexing
+    // crossed into another page
+    fn get_operand<B: Bus>(&self, bus: &mut B, mode: AddressingMode) -> (Operand, bool) {
         match mode {
-            AddressingMode::Absolute | AddressingMode::AbsoluteIndirect => {
-                let low = memory.read(self.program_counter + 1);
-                let high = memory.read(self.program_counter + 2);
+            AddressingMode::Absolute => (Operand::Address(self.operand_word(bus)), false),
+            AddressingMode::AbsoluteIndirect => {
+                // The pointer's high byte is read without carrying into the next page,
+                // so JMP ($10FF) reads $10FF and $1000
+                let pointer = self.operand_word(bus);
+                let low = bus.read(pointer);
+                let high = bus.read((pointer & 0xFF00) | (pointer.wrapping_add(1) & 0x00FF));
                 let address = u16::from_be_bytes([high, low]);
                 (Operand::Address(address), false)
             }
             AddressingMode::AbsoluteX => {
-                let low = memory.read(self.program_counter + 1);
-                let high = memory.read(self.program_counter + 2);
-                let address = u16::from_be_bytes([high, low]) + self.index_x as u16;
-                let page_crossed = self.program_counter & 0xFF00 != address & 0xFF00;
+                let base = self.operand_word(bus);
+                let address = base.wrapping_add(self.index_x as u16);
+                let page_crossed = base & 0xFF00 != address & 0xFF00;
                 (Operand::Address(address), page_crossed)
             }
             AddressingMode::AbsoluteY => {
-                let low = memory.read(self.program_counter + 1);
-                let high = memory.read(self.program_counter + 2);
-                let address = u16::from_be_bytes([high, low]) + self.index_y as u16;
-                let page_crossed = self.program_counter & 0xFF00 != address & 0xFF00;
+                let base = self.operand_word(bus);
+                let address = base.wrapping_add(self.index_y as u16);
+                let page_crossed = base & 0xFF00 != address & 0xFF00;
                 (Operand::Address(address), page_crossed)
             }
             AddressingMode::ZeroPage => {
-                let address = memory.read(self.program_counter + 1);
+                let address = self.operand_byte(bus);
                 (Operand::Address(address as u16), false)
             }
             AddressingMode::ZeroPageIndexedIndirectX => {
-                let address: u8 = memory
-                    .read(self.program_counter + 1)
-                    .wrapping_add(self.index_x);
-                let low = memory.read(address as u16);
-                let high = memory.read(address.wrapping_add(1) as u16);
+                let address: u8 = self.operand_byte(bus).wrapping_add(self.index_x);
+                let low = bus.read(address as u16);
+                let high = bus.read(address.wrapping_add(1) as u16);
                 let address = u16::from_be_bytes([high, low]);
                 (Operand::Address(address), false)
             }
             AddressingMode::ZeroPageX => {
-                let address: u8 = memory
-                    .read(self.program_counter + 1)
-                    .wrapping_add(self.index_x);
+                let address: u8 = self.operand_byte(bus).wrapping_add(self.index_x);
                 (Operand::Address(address as u16), false)
             }
             AddressingMode::ZeroPageY => {
-                let address: u8 = memory
-                    .read(self.program_counter + 1)
-                    .wrapping_add(self.index_y);
+                let address: u8 = self.operand_byte(bus).wrapping_add(self.index_y);
                 (Operand::Address(address as u16), false)
             }
             AddressingMode::ZeroPageIndirectIndexedY => {
-                let address: u8 = memory.read(self.program_counter);
-                let low = memory.read(address as u16);
-                let high = memory.read(address.wrapping_add(1) as u16);
-                let address = u16::from_be_bytes([high, low]).wrapping_add(self.index_y as u16);
-                (Operand::Address(address), false)
+                let address: u8 = self.operand_byte(bus);
+                let low = bus.read(address as u16);
+                let high = bus.read(address.wrapping_add(1) as u16);
+                let base = u16::from_be_bytes([high, low]);
+                let address = base.wrapping_add(self.index_y as u16);
+                let page_crossed = base & 0xFF00 != address & 0xFF00;
+                (Operand::Address(address), page_crossed)
             }
             AddressingMode::Immediate => {
-                let immediate: u8 = memory.read(self.program_counter + 1);
+                let immediate: u8 = self.operand_byte(bus);
                 (Operand::Immediate(immediate), false)
             }
             AddressingMode::Relative => {
-                let low = memory.read(self.program_counter + 1);
-                let high = memory.read(self.program_counter + 2);
-                let offset = u16::from_be_bytes([high, low]);
+                let offset = self.operand_byte(bus) as i8;
                 (Operand::Offset(offset), false)
             }
+            AddressingMode::Accumulator => (Operand::Accumulator, false),
             AddressingMode::Implied => (Operand::Implied, false),
         }
     }
 
-    fn move_program_counter(&mut self, mode: AddressingMode) -> usize {
-        let step = match mode {
-            AddressingMode::Absolute
-            | AddressingMode::AbsoluteIndirect
-            | AddressingMode::AbsoluteX
-            | AddressingMode::AbsoluteY => 3,
-            AddressingMode::ZeroPage
-            | AddressingMode::ZeroPageX
-            | AddressingMode::ZeroPageY
-            | AddressingMode::ZeroPageIndirectIndexedY
-            | AddressingMode::ZeroPageIndexedIndirectX
-            | AddressingMode::Immediate
-            | AddressingMode::Relative => 2,
-            AddressingMode::Implied => 1,
-        };
-        let prev_counter = self.program_counter;
-        self.program_counter = self.program_counter.wrapping_add(step);
-        (self.program_counter >> 8 != prev_counter >> 8) as usize
+    fn operand_byte<B: Bus>(&self, bus: &mut B) -> u8 {
+        bus.read(self.program_counter.wrapping_add(1))
+    }
+
+    fn operand_word<B: Bus>(&self, bus: &mut B) -> u16 {
+        let low = bus.read(self.program_counter.wrapping_add(1));
+        let high = bus.read(self.program_counter.wrapping_add(2));
+        u16::from_be_bytes([high, low])
+    }
+
+    fn move_program_counter(&mut self, mode: AddressingMode) {
+        self.program_counter = self.program_counter.wrapping_add(mode.instruction_length());
     }
 }
 
 enum Operand {
     Address(u16),
     Immediate(u8),
-    Offset(u16),
+    Offset(i8),
+    Accumulator,
     Implied,
 }
-
-impl Operand {
-    pub fn read(&self, memory: &CpuMemory) -> u8 {
-        match self {
-            Operand::Address(v) => memory.read(*v),
-            Operand::Immediate(v) => *v,
-            _ => panic!("Tried to read the value of a non-operand value"),
-        }
-    }
-}
@@ -0,0 +1,47 @@
+/* https://www.nesdev.org/wiki/Status_flags
+* The P register only stores six flags. Bit 5 has no storage and always reads as set, and
+* the B flag (bit 4) only exists in the copy of P pushed to the stack: it is set when the
+* push comes from PHP or BRK and clear when it comes from an IRQ or NMI. This is the only
+* way for an interrupt handler to tell BRK apart from a hardware interrupt.
+*/
+
+pub const CARRY: u8 = 0b00000001;
+pub const ZERO: u8 = 0b00000010;
+pub const INTERRUPT_DISABLE: u8 = 0b00000100;
+pub const DECIMAL: u8 = 0b00001000;
+pub const BREAK: u8 = 0b00010000;
+pub const UNUSED: u8 = 0b00100000;
+pub const OVERFLOW: u8 = 0b01000000;
+pub const SIGN: u8 = 0b10000000;
+
+impl super::Mos6502 {
+    // Packs the flags into the byte that gets pushed to the stack. `brk` is true for
+    // pushes done by PHP and BRK, debuggers and trace logs show it with B clear.
+    pub fn status(&self, brk: bool) -> u8 {
+        let mut status = UNUSED;
+        for (flag, set) in [
+            (CARRY, self.carry),
+            (ZERO, self.zero),
+            (INTERRUPT_DISABLE, !self.interrupt_enable),
+            (DECIMAL, self.decimal_mode),
+            (BREAK, brk),
+            (OVERFLOW, self.overflow),
+            (SIGN, self.sign),
+        ] {
+            if set {
+                status |= flag;
+            }
+        }
+        status
+    }
+
+    // Unpacks a byte pulled by PLP or RTI. B and bit 5 have nowhere to go and are dropped.
+    pub fn set_status(&mut self, status: u8) {
+        self.carry = status & CARRY != 0;
+        self.zero = status & ZERO != 0;
+        self.interrupt_enable = status & INTERRUPT_DISABLE == 0;
+        self.decimal_mode = status & DECIMAL != 0;
+        self.overflow = status & OVERFLOW != 0;
+        self.sign = status & SIGN != 0;
+    }
+}
@@ -0,0 +1,60 @@
+// The assembler and disassembler use the same tables in opposite directions, so anything
+// the disassembler prints should assemble back to the same instruction. Opcodes that are
+// duplicates of another, like the JAMs and some unofficial NOPs, may come back as the other.
+
+use super::super::{
+    assembler::assemble, disassembler::disassemble_bytes, instruction_table::unofficial,
+};
+
+#[test]
+fn disassembly_assembles_back() {
+    for opcode in 0..=0xFF {
+        let bytes = [opcode, 0x12, 0x34];
+        let disassembly = disassemble_bytes(&bytes, 0x8000).unwrap();
+        let text = disassembly.to_string();
+        let assembly = assemble(&format!(".org $8000\n{text}"))
+            .unwrap_or_else(|error| panic!("{opcode:02x} {text}: {error}"));
+        let bytes = assembly.bytes();
+        let reassembled = disassemble_bytes(&bytes, 0x8000).unwrap().to_string();
+        assert_eq!(
+            reassembled, text,
+            "{opcode:02x} {text} assembled differently"
+        );
+        if !unofficial(opcode) {
+            assert_eq!(
+                bytes, disassembly.bytes,
+                "{opcode:02x} {text} assembled differently"
+            );
+        }
+    }
+}
+
+#[test]
+fn labels_and_directives() {
+    let source = "
+        .org $8000
+count = 4
+start:  ldx #count - 1      ; counts down to 0
+loop:   lda table,x
+        sta $0200,x
+        sta <table,y
+        dex
+        bpl loop
+        jmp (vector)
+table:  .byte 1, $10 | 5, %11, 'a', \"bc\"
+vector: .word start, >start, *
+";
+    let assembly = assemble(source).unwrap();
+    assert_eq!(assembly.labels["table"], 0x8011);
+    assert_eq!(
+        assembly.segments,
+        vec![(
+            0x8000,
+            vec![
+                0xA2, 0x03, 0xBD, 0x11, 0x80, 0x9D, 0x00, 0x02, 0x99, 0x11, 0x00, 0xCA, 0x10, 0xF4,
+                0x6C, 0x17, 0x80, 0x01, 0x15, 0x03, 0x61, 0x62, 0x63, 0x00, 0x80, 0x80, 0x00, 0x17,
+                0x80,
+            ]
+        )]
+    );
+}
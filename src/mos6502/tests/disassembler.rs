@@ -0,0 +1,87 @@
+// Checks the operand formats listed at the top of disassembler.rs, one per addressing mode
+
+use super::super::disassembler::{disassemble, disassemble_all, disassemble_bytes};
+use super::FlatMemory;
+
+fn text(bytes: &[u8], address: u16) -> String {
+    disassemble_bytes(bytes, address).unwrap().to_string()
+}
+
+#[test]
+fn addressing_modes() {
+    for (bytes, expected) in [
+        (&[0xEA][..], "NOP"),
+        (&[0x0A], "ASL A"),
+        (&[0xA9, 0x10], "LDA #$10"),
+        (&[0xA5, 0x10], "LDA $10"),
+        (&[0xB5, 0x10], "LDA $10,X"),
+        (&[0xB6, 0x10], "LDX $10,Y"),
+        (&[0xAD, 0x34, 0x12], "LDA $1234"),
+        (&[0xBD, 0x34, 0x12], "LDA $1234,X"),
+        (&[0xB9, 0x34, 0x12], "LDA $1234,Y"),
+        (&[0x6C, 0x34, 0x12], "JMP ($1234)"),
+        (&[0xA1, 0x10], "LDA ($10,X)"),
+        (&[0xB1, 0x10], "LDA ($10),Y"),
+    ] {
+        assert_eq!(text(bytes, 0x8000), expected);
+        assert_eq!(
+            disassemble_bytes(bytes, 0x8000).unwrap().length(),
+            bytes.len() as u16
+        );
+    }
+}
+
+#[test]
+fn branch_targets() {
+    // Offsets count from the instruction after the branch
+    assert_eq!(text(&[0xD0, 0x10], 0xC000), "BNE $C012");
+    assert_eq!(text(&[0xD0, 0xFE], 0xC000), "BNE $C000");
+    assert_eq!(text(&[0x10, 0x80], 0xC000), "BPL $BF82");
+    // Wraps around the ends of the address space
+    assert_eq!(text(&[0xF0, 0x10], 0xFFF8), "BEQ $000A");
+    assert_eq!(text(&[0x90, 0xF0], 0x0004), "BCC $FFF6");
+}
+
+#[test]
+fn unofficial_mnemonics() {
+    for (bytes, expected) in [
+        (&[0xA7, 0x10][..], "*LAX $10"),
+        (&[0xAB, 0x10], "*LXA #$10"),
+        (&[0x8B, 0x10], "*XAA #$10"),
+        (&[0xC7, 0x10], "*DCP $10"),
+        (&[0xEB, 0x10], "*SBC #$10"),
+        (&[0x1A], "*NOP"),
+        (&[0x04, 0x10], "*NOP $10"),
+        (&[0x1C, 0x34, 0x12], "*NOP $1234,X"),
+        (&[0x02], "*STP"),
+        (&[0x93, 0x10], "*AHX ($10),Y"),
+    ] {
+        assert_eq!(text(bytes, 0x8000), expected);
+    }
+    // The official encodings of the same names don't get the *
+    assert_eq!(text(&[0xEA], 0x8000), "NOP");
+    assert_eq!(text(&[0xE9, 0x10], 0x8000), "SBC #$10");
+}
+
+#[test]
+fn cut_off_instructions() {
+    assert!(disassemble_bytes(&[0xAD, 0x34], 0x8000).is_none());
+    assert!(disassemble_bytes(&[], 0x8000).is_none());
+    let all = disassemble_all(&[0xA9, 0x01, 0xE8, 0x8D, 0x00], 0x8000);
+    let all: Vec<_> = all.iter().map(|d| (d.address, d.to_string())).collect();
+    assert_eq!(
+        all,
+        [(0x8000, "LDA #$01".to_owned()), (0x8002, "INX".to_owned())]
+    );
+}
+
+// Reads from the bus go through peek, so nothing sees them
+#[test]
+fn from_the_bus() {
+    let mut memory = FlatMemory::new();
+    memory.ram[0xFFFF] = 0x4C;
+    memory.ram[0x0000] = 0x34;
+    memory.ram[0x0001] = 0x12;
+    assert_eq!(disassemble(&memory, 0xFFFF).to_string(), "JMP $1234");
+    assert!(memory.accesses.is_empty());
+}
@@ -0,0 +1,79 @@
+// OAM DMA halts the CPU after the write to $4014, for one cycle more when that write lands
+// on an odd cycle. The copy itself is up to the bus, this only checks the CPU waits.
+
+use super::super::{bus::Bus, Mos6502, Variant};
+use super::FlatMemory;
+
+// Flat memory that starts a DMA on every write to $4014, and counts the cycles it sees
+struct DmaMemory {
+    memory: FlatMemory,
+    dma: bool,
+    ticks: u64,
+}
+
+impl Bus for DmaMemory {
+    fn read(&mut self, address: u16) -> u8 {
+        self.memory.read(address)
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.dma |= address == 0x4014;
+        self.memory.write(address, value);
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.memory.peek(address)
+    }
+
+    fn tick(&mut self) {
+        self.ticks += 1;
+    }
+
+    fn take_dma(&mut self) -> bool {
+        std::mem::take(&mut self.dma)
+    }
+}
+
+// Runs STA $4014 after the instructions in `before`, returns the cycles the STA took on
+// each core
+fn sta_cycles(before: &[u8], instructions: usize) -> (u64, u64) {
+    let mut program = before.to_vec();
+    program.extend([0x8D, 0x14, 0x40]);
+    let mut cycles = [0; 2];
+    for (tick, cycles) in [false, true].into_iter().zip(&mut cycles) {
+        let mut bus = DmaMemory {
+            memory: FlatMemory::new(),
+            dma: false,
+            ticks: 0,
+        };
+        bus.memory.ram[0x0200..0x0200 + program.len()].copy_from_slice(&program);
+        let mut cpu = Mos6502::new(Variant::Ricoh2A03);
+        cpu.run_instruction(&mut bus);
+        cpu.program_counter = 0x0200;
+        for _ in 0..=instructions {
+            let start = cpu.total_cycles();
+            if tick {
+                while !cpu.tick(&mut bus) {}
+            } else {
+                cpu.run_instruction(&mut bus);
+            }
+            *cycles = cpu.total_cycles() - start;
+        }
+        assert_eq!(bus.ticks, cpu.total_cycles(), "every cycle reaches the bus");
+    }
+    (cycles[0], cycles[1])
+}
+
+#[test]
+fn stall() {
+    // NOP takes 2 cycles and NOP $00 takes 3, so only the second moves the STA's write to a
+    // cycle of the other parity
+    let first = sta_cycles(&[], 0);
+    let second = sta_cycles(&[0x04, 0x00], 1);
+    assert_eq!(first.0, first.1, "the cores disagree");
+    assert_eq!(second.0, second.1, "the cores disagree");
+    assert_eq!(sta_cycles(&[0xEA], 1), first);
+    let mut both = [first.0, second.0];
+    both.sort();
+    assert_eq!(both, [4 + 513, 4 + 514]);
+}
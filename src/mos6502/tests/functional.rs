@@ -0,0 +1,97 @@
+/* https://github.com/Klaus2m5/6502_65C02_functional_tests
+* Both tests end by jumping or branching to themselves, whether they passed or failed,
+* so the address of that trap says how it went. Point KLAUS_DORMANN at a directory holding
+* 6502_functional_test.bin and 6502_decimal_test.bin and run
+* `cargo test --release -- --ignored functional decimal`.
+*/
+
+use std::{env, fs, path::Path};
+
+use super::super::{Mos6502, Variant};
+use super::FlatMemory;
+
+// Where the functional test traps once every test passed, for the binary shipped in the repo
+const FUNCTIONAL_SUCCESS: u16 = 0x3469;
+// The decimal test writes 0 here once every result matched
+const DECIMAL_ERROR: usize = 0x000B;
+
+// Far more than either test needs, a bug that skips the trap shouldn't hang the test run
+const INSTRUCTION_LIMIT: usize = 200_000_000;
+
+fn load(name: &str) -> Vec<u8> {
+    let directory = env::var("KLAUS_DORMANN").expect("KLAUS_DORMANN isn't set");
+    fs::read(Path::new(&directory).join(name)).unwrap_or_else(|_| panic!("can't read {name}"))
+}
+
+// Runs the reset sequence and then jumps to the start of the test
+fn start(mut memory: FlatMemory, program_counter: u16) -> (Mos6502, FlatMemory) {
+    let mut cpu = Mos6502::new(Variant::Nmos6502);
+    cpu.run_instruction(&mut memory);
+    cpu.program_counter = program_counter;
+    (cpu, memory)
+}
+
+// Runs until an instruction leaves the program counter where it was, or the CPU jams, and
+// returns the address it got stuck at. `tick` picks the cycle stepped core.
+fn run_until_trap(cpu: &mut Mos6502, memory: &mut FlatMemory, tick: bool) -> u16 {
+    for _ in 0..INSTRUCTION_LIMIT {
+        let program_counter = cpu.program_counter;
+        if tick {
+            while !cpu.tick(memory) {}
+        } else {
+            cpu.run_instruction(memory);
+        }
+        // Nothing looks at the bus activity, don't let it pile up
+        memory.accesses.clear();
+        if cpu.program_counter == program_counter || cpu.is_halted() {
+            return program_counter;
+        }
+    }
+    panic!("no trap after {INSTRUCTION_LIMIT} instructions");
+}
+
+fn functional(tick: bool) {
+    let image = load("6502_functional_test.bin");
+    let mut memory = FlatMemory::new();
+    memory.ram[..image.len()].copy_from_slice(&image);
+    let (mut cpu, mut memory) = start(memory, 0x0400);
+    let trap = run_until_trap(&mut cpu, &mut memory, tick);
+    println!("trapped at ${trap:04X}");
+    assert_eq!(
+        trap, FUNCTIONAL_SUCCESS,
+        "trapped at ${trap:04X}, see the listing for which test that is"
+    );
+}
+
+#[test]
+#[ignore = "needs KLAUS_DORMANN"]
+fn functional_run_instruction() {
+    functional(false);
+}
+
+#[test]
+#[ignore = "needs KLAUS_DORMANN"]
+fn functional_tick() {
+    functional(true);
+}
+
+#[test]
+#[ignore = "needs KLAUS_DORMANN"]
+fn decimal() {
+    let image = load("6502_decimal_test.bin");
+    let mut memory = FlatMemory::new();
+    if image.len() == 0x10000 {
+        memory.ram.copy_from_slice(&image);
+    } else {
+        // Assembled on its own the test starts at its origin, $0200. It ends with a BRK,
+        // so point the BRK vector at a JMP to itself to turn that into a trap.
+        memory.ram[0x0200..0x0200 + image.len()].copy_from_slice(&image);
+        memory.ram[0xFFF0..0xFFF3].copy_from_slice(&[0x4C, 0xF0, 0xFF]);
+        memory.ram[0xFFFE..].copy_from_slice(&[0xF0, 0xFF]);
+    }
+    let (mut cpu, mut memory) = start(memory, 0x0200);
+    let trap = run_until_trap(&mut cpu, &mut memory, false);
+    let error = memory.ram[DECIMAL_ERROR];
+    println!("trapped at ${trap:04X} with ERROR = {error}");
+    assert_eq!(error, 0, "trapped at ${trap:04X} with ERROR = {error}");
+}
@@ -0,0 +1,444 @@
+// Hand written checks of the instructions, so a broken opcode fails the build without the
+// test suites on disk. Every program is run on both cores, which have to end up in the
+// same state after the same number of cycles.
+
+use super::super::{
+    assembler::assemble,
+    instruction_table::{Instruction, INSTRUCTIONS},
+    status::{CARRY, DECIMAL, INTERRUPT_DISABLE, OVERFLOW, SIGN, ZERO},
+    Mos6502, Variant,
+};
+use super::FlatMemory;
+
+// Where the programs get assembled, anything after the first .org is data
+const START: u16 = 0x0200;
+
+struct Run {
+    cpu: Mos6502,
+    memory: FlatMemory,
+    cycles: u64,
+}
+
+// Runs the program at START until the PC leaves it, with the registers set up by `setup`
+// after the reset sequence. `tick` picks the cycle stepped core.
+fn run_on(source: &str, setup: &dyn Fn(&mut Mos6502), tick: bool) -> Run {
+    let assembly = assemble(&format!(".org ${START:04X}\n{source}")).unwrap();
+    let end = START + assembly.segments[0].1.len() as u16;
+    let mut memory = FlatMemory::new();
+    assembly.write_to(&mut memory.ram);
+    let mut cpu = Mos6502::new(Variant::Nmos6502);
+    cpu.run_instruction(&mut memory);
+    cpu.program_counter = START;
+    setup(&mut cpu);
+    let reset = cpu.total_cycles();
+    while (START..end).contains(&cpu.program_counter) && !cpu.is_halted() {
+        if tick {
+            while !cpu.tick(&mut memory) {}
+        } else {
+            cpu.run_instruction(&mut memory);
+        }
+    }
+    let cycles = cpu.total_cycles() - reset;
+    Run {
+        cpu,
+        memory,
+        cycles,
+    }
+}
+
+fn registers(cpu: &Mos6502) -> (u16, u8, u8, u8, u8, u8) {
+    (
+        cpu.program_counter,
+        cpu.accumulator,
+        cpu.index_x,
+        cpu.index_y,
+        cpu.stack_pointer,
+        cpu.status(false),
+    )
+}
+
+fn run(source: &str, setup: impl Fn(&mut Mos6502)) -> Run {
+    let whole = run_on(source, &setup, false);
+    let ticked = run_on(source, &setup, true);
+    assert_eq!(
+        registers(&whole.cpu),
+        registers(&ticked.cpu),
+        "the cores disagree on {source}"
+    );
+    assert!(
+        whole.memory.ram == ticked.memory.ram,
+        "the cores disagree on {source}"
+    );
+    assert_eq!(
+        whole.cycles, ticked.cycles,
+        "the cores disagree on {source}"
+    );
+    whole
+}
+
+// Registers are left as the reset sequence set them
+fn reset(_: &mut Mos6502) {}
+
+// Checks the flags in `mask` against `expected`
+fn assert_flags(cpu: &Mos6502, mask: u8, expected: u8) {
+    assert_eq!(
+        cpu.status(false) & mask,
+        expected,
+        "flags {:08b}",
+        cpu.status(false)
+    );
+}
+
+// Small xorshift so the differential test is the same on every run
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u8 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 as u8
+    }
+}
+
+// Every opcode but the JAMs, from random registers over random memory
+#[test]
+fn cores_agree() {
+    let mut random = Random(0x2A03_6502_0000_0001);
+    let mut ram = vec![0; 0x10000];
+    ram.iter_mut().for_each(|byte| *byte = random.next());
+    for opcode in 0..=0xFF {
+        if INSTRUCTIONS[opcode as usize] == Instruction::STP {
+            continue;
+        }
+        for _ in 0..64 {
+            let mut memory = FlatMemory::new();
+            memory.ram.copy_from_slice(&ram);
+            let mut cpu = Mos6502::new(Variant::Nmos6502);
+            cpu.run_instruction(&mut memory);
+            cpu.program_counter = u16::from_le_bytes([random.next(), random.next()]);
+            cpu.accumulator = random.next();
+            cpu.index_x = random.next();
+            cpu.index_y = random.next();
+            cpu.stack_pointer = random.next();
+            cpu.set_status(random.next());
+            memory.ram[cpu.program_counter as usize] = opcode;
+            let mut ticked_memory = FlatMemory::new();
+            ticked_memory.ram = memory.ram.clone();
+            let mut ticked = Mos6502::new(Variant::Nmos6502);
+            ticked.run_instruction(&mut ticked_memory);
+            ticked.program_counter = cpu.program_counter;
+            ticked.accumulator = cpu.accumulator;
+            ticked.index_x = cpu.index_x;
+            ticked.index_y = cpu.index_y;
+            ticked.stack_pointer = cpu.stack_pointer;
+            ticked.set_status(cpu.status(false));
+            let cycles = cpu.run_instruction(&mut memory);
+            let mut ticks = 1;
+            while !ticked.tick(&mut ticked_memory) {
+                ticks += 1;
+            }
+            assert_eq!(
+                (registers(&cpu), cycles),
+                (registers(&ticked), ticks),
+                "the cores disagree on {opcode:02X}"
+            );
+            assert!(
+                memory.ram == ticked_memory.ram,
+                "the cores disagree on {opcode:02X}"
+            );
+        }
+    }
+}
+
+#[test]
+fn loads_and_stores() {
+    let after = run("lda #$80\nldx #0\nldy #1", reset);
+    let cpu = &after.cpu;
+    assert_eq!(
+        (cpu.accumulator, cpu.index_x, cpu.index_y),
+        (0x80, 0x00, 0x01)
+    );
+    assert_flags(&after.cpu, SIGN | ZERO, 0);
+    let after = run("lda #$5A\nsta $10\nstx $0300\nsty $11,x", |cpu| {
+        cpu.index_x = 0x22;
+        cpu.index_y = 0x33;
+    });
+    assert_eq!(after.memory.ram[0x10], 0x5A);
+    assert_eq!(after.memory.ram[0x0300], 0x22);
+    assert_eq!(after.memory.ram[0x33], 0x33);
+    assert_eq!(after.cycles, 2 + 3 + 4 + 4);
+}
+
+#[test]
+fn addressing_modes() {
+    // Zero page indexing wraps around inside the zero page
+    let after = run("lda $F0,x\n.org $10\n.byte $11", |cpu| cpu.index_x = 0x20);
+    assert_eq!(after.cpu.accumulator, 0x11);
+    // (zp,x) and (zp),y, the second crossing a page
+    let after = run(
+        "lda ($20,x)\ntax\nlda ($30),y
+        .org $24\n.word $3000\n.org $30\n.word $30F0
+        .org $3000\n.byte $77\n.org $3110\n.byte $66",
+        |cpu| {
+            cpu.index_x = 0x04;
+            cpu.index_y = 0x20;
+        },
+    );
+    assert_eq!(after.cpu.index_x, 0x77);
+    assert_eq!(after.cpu.accumulator, 0x66);
+    assert_eq!(after.cycles, 6 + 2 + 6);
+    // Reads only take the extra cycle when they cross a page, stores always do
+    let after = run("lda $30F0,y\nlda $3000,y\nsta $3000,y", |cpu| {
+        cpu.index_y = 0x20
+    });
+    assert_eq!(after.cycles, 5 + 4 + 5);
+    // JMP (ind) doesn't carry into the high byte of the pointer
+    let after = run(
+        "jmp ($10FF)\n.org $10FF\n.byte $56\n.org $1000\n.byte $34",
+        reset,
+    );
+    assert_eq!(after.cpu.program_counter, 0x3456);
+}
+
+#[test]
+fn arithmetic() {
+    let after = run("clc\nlda #$50\nadc #$50", reset);
+    assert_eq!(after.cpu.accumulator, 0xA0);
+    assert_flags(&after.cpu, CARRY | OVERFLOW | SIGN | ZERO, OVERFLOW | SIGN);
+    let after = run("sec\nlda #$FE\nadc #$01", reset);
+    assert_eq!(after.cpu.accumulator, 0x00);
+    assert_flags(&after.cpu, CARRY | OVERFLOW | SIGN | ZERO, CARRY | ZERO);
+    let after = run("sec\nlda #$50\nsbc #$B0", reset);
+    assert_eq!(after.cpu.accumulator, 0xA0);
+    assert_flags(&after.cpu, CARRY | OVERFLOW | SIGN | ZERO, OVERFLOW | SIGN);
+    let after = run("sed\nclc\nlda #$19\nadc #$01", reset);
+    assert_eq!(after.cpu.accumulator, 0x20);
+    let after = run("lda #$40\ncmp #$41", reset);
+    assert_flags(&after.cpu, CARRY | SIGN | ZERO, SIGN);
+    let after = run("ldx #$40\ncpx #$40", reset);
+    assert_flags(&after.cpu, CARRY | SIGN | ZERO, CARRY | ZERO);
+    let after = run("lda #$F0\nand #$3C\nora #$01\neor #$FF", reset);
+    assert_eq!(after.cpu.accumulator, 0xCE);
+    let after = run("bit $10\n.org $10\n.byte $C0", |cpu| cpu.accumulator = 0x3F);
+    assert_flags(&after.cpu, OVERFLOW | SIGN | ZERO, OVERFLOW | SIGN | ZERO);
+}
+
+// The 2A03 keeps the D flag but leaves out the decimal mode it selects
+#[test]
+fn decimal_mode() {
+    for (variant, sum, difference) in [
+        (Variant::Nmos6502, (0x00, CARRY), 0x19),
+        (Variant::Ricoh2A03, (0x9A, 0), 0x1F),
+    ] {
+        let setup = |cpu: &mut Mos6502| cpu.variant = variant;
+        let after = run("sed\nclc\nlda #$99\nadc #$01", setup);
+        assert_eq!(after.cpu.accumulator, sum.0);
+        assert_flags(&after.cpu, CARRY | DECIMAL, sum.1 | DECIMAL);
+        let after = run("sed\nsec\nlda #$20\nsbc #$01", setup);
+        assert_eq!(after.cpu.accumulator, difference);
+    }
+}
+
+#[test]
+fn read_modify_write() {
+    let after = run(
+        "clc\nasl $10\nror $11\ninc $12
+        .org $10\n.byte $81, $01, $FF",
+        reset,
+    );
+    assert_eq!(after.memory.ram[0x10..0x13], [0x02, 0x80, 0x00]);
+    assert_flags(&after.cpu, CARRY | SIGN | ZERO, CARRY | ZERO);
+    assert_eq!(after.cycles, 2 + 5 + 5 + 5);
+    let after = run("sec\nlda #$81\nrol a\nlsr a", reset);
+    assert_eq!(after.cpu.accumulator, 0x01);
+    assert_flags(&after.cpu, CARRY, CARRY);
+    let after = run("inc $3000,x", |cpu| cpu.index_x = 0);
+    assert_eq!(after.cycles, 7);
+}
+
+#[test]
+fn branches() {
+    let after = run("lda #1\nbeq skip\nnop\nskip:", reset);
+    assert_eq!(after.cycles, 2 + 2 + 2);
+    let after = run("lda #1\nbne skip\nnop\nskip:", reset);
+    assert_eq!(after.cycles, 2 + 3);
+    // Leaves the program backwards, onto the page before it
+    let after = run("lda #1\nbne $01F0", reset);
+    assert_eq!(after.cpu.program_counter, 0x01F0);
+    assert_eq!(after.cycles, 2 + 4);
+}
+
+#[test]
+fn stack() {
+    let after = run("jsr sub\njmp $4000\nsub: lda #7\nrts", |cpu| {
+        cpu.stack_pointer = 0xFF
+    });
+    assert_eq!(after.cpu.program_counter, 0x4000);
+    assert_eq!(after.cpu.accumulator, 7);
+    assert_eq!(after.cpu.stack_pointer, 0xFF);
+    // The address of the last byte of the JSR
+    assert_eq!(after.memory.ram[0x01FE..0x0200], [0x02, 0x02]);
+    assert_eq!(after.cycles, 6 + 2 + 6 + 3);
+    let after = run("sec\nphp\nlda #$80\npha\nlda #0\npla\nplp", |cpu| {
+        cpu.stack_pointer = 0xFF;
+        cpu.set_status(0);
+    });
+    // PHP pushes B and bit 5 set
+    assert_eq!(after.memory.ram[0x01FE..0x0200], [0x80, 0x31]);
+    assert_eq!(after.cpu.accumulator, 0x80);
+    assert_eq!(after.cpu.status(false), 0x21);
+    let after = run("tsx\ndex\ntxs", |cpu| cpu.stack_pointer = 0x00);
+    assert_eq!(after.cpu.stack_pointer, 0xFF);
+    assert_flags(&after.cpu, SIGN | ZERO, SIGN);
+}
+
+#[test]
+fn brk_and_rti() {
+    let after = run("brk\n.org $FFFE\n.word $0300", |cpu| {
+        cpu.stack_pointer = 0xFF;
+        cpu.set_status(CARRY);
+    });
+    assert_eq!(after.cpu.program_counter, 0x0300);
+    assert_flags(&after.cpu, INTERRUPT_DISABLE, INTERRUPT_DISABLE);
+    // The byte after BRK is skipped, and B is set in the pushed flags
+    assert_eq!(after.memory.ram[0x01FD..0x0200], [0x31, 0x02, 0x02]);
+    assert_eq!(after.cycles, 7);
+    let after = run("rti\n.org $0101\n.byte $C3, $34, $12", |cpu| {
+        cpu.stack_pointer = 0x00
+    });
+    assert_eq!(after.cpu.program_counter, 0x1234);
+    assert_eq!(after.cpu.status(false), 0xE3);
+    assert_eq!(after.cycles, 6);
+}
+
+#[test]
+fn unofficial_read_modify_write() {
+    let data = ".org $10\n.byte $41, $0F, $81, $81, $03, $02";
+    let after = run(&format!("dcp $10\n{data}"), |cpu| cpu.accumulator = 0x40);
+    assert_eq!(after.memory.ram[0x10], 0x40);
+    assert_flags(&after.cpu, CARRY | ZERO, CARRY | ZERO);
+    let after = run(&format!("sec\nisc $11\n{data}"), |cpu| {
+        cpu.accumulator = 0x20
+    });
+    assert_eq!(
+        (after.memory.ram[0x11], after.cpu.accumulator),
+        (0x10, 0x10)
+    );
+    assert_flags(&after.cpu, CARRY, CARRY);
+    let after = run(&format!("slo $12\n{data}"), |cpu| cpu.accumulator = 0x01);
+    assert_eq!(
+        (after.memory.ram[0x12], after.cpu.accumulator),
+        (0x02, 0x03)
+    );
+    assert_flags(&after.cpu, CARRY, CARRY);
+    let after = run(&format!("clc\nrla $13\n{data}"), |cpu| {
+        cpu.accumulator = 0xFF
+    });
+    assert_eq!(
+        (after.memory.ram[0x13], after.cpu.accumulator),
+        (0x02, 0x02)
+    );
+    assert_flags(&after.cpu, CARRY, CARRY);
+    let after = run(&format!("sre $14\n{data}"), |cpu| cpu.accumulator = 0x10);
+    assert_eq!(
+        (after.memory.ram[0x14], after.cpu.accumulator),
+        (0x01, 0x11)
+    );
+    assert_flags(&after.cpu, CARRY, CARRY);
+    let after = run(&format!("clc\nrra $15\n{data}"), |cpu| {
+        cpu.accumulator = 0x10
+    });
+    assert_eq!(
+        (after.memory.ram[0x15], after.cpu.accumulator),
+        (0x01, 0x11)
+    );
+    assert_flags(&after.cpu, CARRY, 0);
+    // Unofficial RMW instructions in indexed modes always take the fixup cycle
+    let after = run("dcp ($20),y", |cpu| cpu.index_y = 0);
+    assert_eq!(after.cycles, 8);
+}
+
+#[test]
+fn unofficial_reads_and_stores() {
+    let after = run("lax $10\n.org $10\n.byte $99", reset);
+    assert_eq!((after.cpu.accumulator, after.cpu.index_x), (0x99, 0x99));
+    assert_flags(&after.cpu, SIGN, SIGN);
+    let after = run("sax $10", |cpu| {
+        cpu.accumulator = 0xF0;
+        cpu.index_x = 0x3C;
+    });
+    assert_eq!(after.memory.ram[0x10], 0x30);
+    let after = run("anc #$80", |cpu| cpu.accumulator = 0xFF);
+    assert_eq!(after.cpu.accumulator, 0x80);
+    assert_flags(&after.cpu, CARRY | SIGN, CARRY | SIGN);
+    let after = run("alr #$03", |cpu| cpu.accumulator = 0xFF);
+    assert_eq!(after.cpu.accumulator, 0x01);
+    assert_flags(&after.cpu, CARRY, CARRY);
+    let after = run("clc\narr #$FF", |cpu| cpu.accumulator = 0xC0);
+    assert_eq!(after.cpu.accumulator, 0x60);
+    assert_flags(&after.cpu, CARRY | OVERFLOW, CARRY);
+    let after = run("axs #$10", |cpu| {
+        cpu.accumulator = 0xF0;
+        cpu.index_x = 0x3F;
+    });
+    assert_eq!(after.cpu.index_x, 0x20);
+    assert_flags(&after.cpu, CARRY, CARRY);
+    let after = run("las $3000,y\n.org $3000\n.byte $F3", |cpu| {
+        cpu.index_y = 0;
+        cpu.stack_pointer = 0x3F;
+    });
+    let cpu = &after.cpu;
+    assert_eq!(
+        (cpu.accumulator, cpu.index_x, cpu.stack_pointer),
+        (0x33, 0x33, 0x33)
+    );
+    // ($00 | $EE) & $5A & $FF
+    let after = run("xaa #$FF", |cpu| {
+        cpu.accumulator = 0x00;
+        cpu.index_x = 0x5A;
+    });
+    assert_eq!(after.cpu.accumulator, 0x4A);
+    // The unofficial NOPs still read their operand and take the page cross cycle
+    let after = run("*nop $30F0,x", |cpu| cpu.index_x = 0x20);
+    assert_eq!(after.cycles, 5);
+}
+
+// LAX #imm mixes in the unstable value the same way XAA does
+#[test]
+fn lxa() {
+    assert_eq!(assemble("lxa #0").unwrap().bytes(), [0xAB, 0x00]);
+    // ($01 | $EE) & $5B
+    let after = run("lxa #$5B", |cpu| cpu.accumulator = 0x01);
+    assert_eq!(after.cpu.accumulator, 0x4B);
+    assert_eq!(after.cpu.index_x, after.cpu.accumulator);
+    let after = run("lxa #$80", |cpu| cpu.accumulator = 0x01);
+    assert_eq!(after.cpu.accumulator, 0x80);
+    assert_flags(&after.cpu, SIGN, SIGN);
+    let after = run("lxa #$01", |cpu| cpu.accumulator = 0x00);
+    assert_eq!(after.cpu.accumulator, 0x00);
+    assert_flags(&after.cpu, ZERO, ZERO);
+}
+
+#[test]
+fn jam() {
+    let after = run("stp\nnop", reset);
+    assert!(after.cpu.is_halted());
+    assert_eq!(after.cpu.program_counter, START + 1);
+    // Both cores keep reading $FFFF once a cycle, so loops counting cycles still end
+    for tick in [false, true] {
+        let mut after = run_on("stp", &reset, tick);
+        let start = after.cpu.total_cycles();
+        after.memory.accesses.clear();
+        for _ in 0..3 {
+            let cycles = if tick {
+                after.cpu.tick(&mut after.memory) as usize
+            } else {
+                after.cpu.run_instruction(&mut after.memory)
+            };
+            assert_eq!(cycles, 1);
+        }
+        assert_eq!(after.cpu.total_cycles() - start, 3);
+        assert_eq!(after.memory.accesses, [(0xFFFF, 0, "read"); 3]);
+    }
+}
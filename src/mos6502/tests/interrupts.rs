@@ -0,0 +1,214 @@
+// Reset, NMI and IRQ, see the rules at the top of interrupts.rs. The lines are changed
+// between instructions, and every test runs on both cores, which have to end up in the same
+// state after the same number of cycles.
+
+use super::super::{assembler::assemble, status::BREAK, Mos6502, Variant};
+use super::FlatMemory;
+
+// Memory is all NOPs, so the handlers at $0300 (NMI) and $0400 (IRQ and BRK) just run on
+// unless a program puts something there. Reset goes to the program at $0200.
+const VECTORS: &str = ".org $FFFA\n.word $0300, $0200, $0400";
+
+struct Cpu {
+    cpu: Mos6502,
+    memory: FlatMemory,
+    tick: bool,
+}
+
+impl Cpu {
+    // Runs the reset sequence, which leaves I set and the stack pointer at $FD
+    fn new(source: &str, tick: bool) -> Self {
+        let assembly = assemble(&format!(".org $0200\n{source}\n{VECTORS}")).unwrap();
+        let mut memory = FlatMemory::new();
+        memory.ram.fill(0xEA);
+        assembly.write_to(&mut memory.ram);
+        let mut cpu = Cpu {
+            cpu: Mos6502::new(Variant::Ricoh2A03),
+            memory,
+            tick,
+        };
+        cpu.step();
+        cpu
+    }
+
+    // Runs one instruction, or one interrupt sequence
+    fn step(&mut self) {
+        if self.tick {
+            while !self.cpu.tick(&mut self.memory) {}
+        } else {
+            self.cpu.run_instruction(&mut self.memory);
+        }
+    }
+
+    fn pc(&self) -> u16 {
+        self.cpu.program_counter
+    }
+
+    // The status and return address pushed by the last interrupt or BRK
+    fn pushed(&self) -> (u8, u16) {
+        let top = 0x0101 + self.cpu.stack_pointer as usize;
+        let ram = &self.memory.ram;
+        (ram[top], u16::from_le_bytes([ram[top + 1], ram[top + 2]]))
+    }
+}
+
+fn on_both_cores(source: &str, test: impl Fn(&mut Cpu)) {
+    let [whole, ticked] = [false, true].map(|tick| {
+        let mut cpu = Cpu::new(source, tick);
+        test(&mut cpu);
+        let state = (cpu.pc(), cpu.cpu.stack_pointer, cpu.cpu.status(false));
+        (state, cpu.cpu.total_cycles(), cpu.memory.ram)
+    });
+    assert_eq!(whole.0, ticked.0, "the cores disagree on {source}");
+    assert_eq!(whole.1, ticked.1, "the cores disagree on {source}");
+    assert!(whole.2 == ticked.2, "the cores disagree on {source}");
+}
+
+#[test]
+fn reset() {
+    on_both_cores("stp", |cpu| {
+        // From power on, where the stack pointer is 0
+        assert_eq!((cpu.pc(), cpu.cpu.stack_pointer), (0x0200, 0xFD));
+        assert!(!cpu.cpu.interrupt_enable);
+        assert_eq!(cpu.cpu.total_cycles(), 7);
+        // Again from a jammed CPU, with I clear
+        cpu.step();
+        assert!(cpu.cpu.is_halted());
+        cpu.cpu.stack_pointer = 0x80;
+        cpu.cpu.set_status(0);
+        cpu.memory.accesses.clear();
+        cpu.cpu.reset();
+        cpu.step();
+        assert!(!cpu.cpu.is_halted());
+        assert_eq!((cpu.pc(), cpu.cpu.stack_pointer), (0x0200, 0x7D));
+        assert!(!cpu.cpu.interrupt_enable);
+        // The three pushes only read the stack
+        let accesses = &cpu.memory.accesses;
+        assert!(accesses.iter().all(|&(_, _, kind)| kind == "read"));
+        assert!(accesses.ends_with(&[(0xFFFC, 0x00, "read"), (0xFFFD, 0x02, "read")]));
+    });
+}
+
+// NMI fires once when the line goes high, however long it stays there, and I doesn't mask it
+#[test]
+fn nmi_edge() {
+    on_both_cores("", |cpu| {
+        cpu.cpu.set_nmi_line(true);
+        cpu.step();
+        assert_eq!(cpu.pc(), 0x0201);
+        cpu.step();
+        assert_eq!(cpu.pc(), 0x0300);
+        assert_eq!(cpu.pushed(), (0x24, 0x0201));
+        for _ in 0..3 {
+            cpu.step();
+        }
+        assert_eq!(cpu.pc(), 0x0303);
+        cpu.cpu.set_nmi_line(false);
+        cpu.step();
+        cpu.cpu.set_nmi_line(true);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.pc(), 0x0300);
+        assert_eq!(cpu.pushed(), (0x24, 0x0305));
+    });
+}
+
+// IRQ is taken for as long as the line is held and I is clear
+#[test]
+fn irq_level() {
+    on_both_cores(".org $0400\nrti", |cpu| {
+        cpu.cpu.set_irq_line(true);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.pc(), 0x0202);
+        cpu.cpu.set_status(0);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.pc(), 0x0400);
+        assert_eq!(cpu.pushed(), (0x20, 0x0203));
+        // RTI clears I straight away, so a line that's still held is right back
+        cpu.step();
+        assert_eq!(cpu.pc(), 0x0203);
+        cpu.step();
+        assert_eq!(cpu.pc(), 0x0400);
+        cpu.cpu.set_irq_line(false);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.pc(), 0x0204);
+    });
+}
+
+// CLI, SEI and PLP change I after the IRQ has been polled for the instruction
+#[test]
+fn delayed_i_flag() {
+    // The instruction after CLI or PLP still runs before the IRQ
+    on_both_cores("cli", |cpu| {
+        cpu.cpu.set_irq_line(true);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.pc(), 0x0202);
+        cpu.step();
+        assert_eq!(cpu.pc(), 0x0400);
+        assert_eq!(cpu.pushed(), (0x20, 0x0202));
+    });
+    on_both_cores("plp\n.org $01FE\n.byte $00", |cpu| {
+        cpu.cpu.set_irq_line(true);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.pc(), 0x0202);
+        cpu.step();
+        assert_eq!(cpu.pc(), 0x0400);
+    });
+    // And one IRQ still gets in after SEI, with I already set in the pushed status
+    on_both_cores("sei", |cpu| {
+        cpu.cpu.set_status(0);
+        cpu.cpu.set_irq_line(true);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.pc(), 0x0400);
+        assert_eq!(cpu.pushed(), (0x24, 0x0201));
+    });
+}
+
+// B is only set in the status pushed by BRK and PHP
+#[test]
+fn break_flag() {
+    on_both_cores("brk", |cpu| {
+        cpu.step();
+        assert_eq!(cpu.pc(), 0x0400);
+        assert_eq!(cpu.pushed(), (0x24 | BREAK, 0x0202));
+    });
+    on_both_cores("", |cpu| {
+        cpu.cpu.set_status(0);
+        cpu.cpu.set_irq_line(true);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.pushed().0 & BREAK, 0);
+    });
+}
+
+// An NMI arriving while BRK or an IRQ is pushing takes over the vector, but the pushed B
+// flag still tells which one it was
+#[test]
+fn nmi_hijack() {
+    on_both_cores("brk", |cpu| {
+        cpu.cpu.set_nmi_line(true);
+        cpu.step();
+        assert_eq!(cpu.pc(), 0x0300);
+        assert_eq!(cpu.pushed(), (0x24 | BREAK, 0x0202));
+        // The NMI was used up
+        cpu.step();
+        assert_eq!(cpu.pc(), 0x0301);
+    });
+    on_both_cores("", |cpu| {
+        cpu.cpu.set_status(0);
+        cpu.cpu.set_irq_line(true);
+        cpu.step();
+        cpu.cpu.set_nmi_line(true);
+        cpu.step();
+        assert_eq!(cpu.pc(), 0x0300);
+        assert_eq!(cpu.pushed(), (0x20, 0x0201));
+        cpu.step();
+        assert_eq!(cpu.pc(), 0x0301);
+    });
+}
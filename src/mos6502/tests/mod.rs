@@ -0,0 +1,46 @@
+// Harnesses for the community CPU test suites. They need the suites on disk so they're
+// ignored by default, each one says where it looks for its files. The hand written tests
+// in instructions run every time.
+
+mod assembler;
+mod disassembler;
+mod dma;
+mod functional;
+mod instructions;
+mod interrupts;
+mod nestest;
+mod single_step;
+
+use super::Bus;
+
+// 64K of RAM with nothing mapped, remembering every access made to it in order
+pub struct FlatMemory {
+    pub ram: Vec<u8>,
+    pub accesses: Vec<(u16, u8, &'static str)>,
+}
+
+impl FlatMemory {
+    pub fn new() -> Self {
+        FlatMemory {
+            ram: vec![0; 0x10000],
+            accesses: Vec::new(),
+        }
+    }
+}
+
+impl Bus for FlatMemory {
+    fn read(&mut self, address: u16) -> u8 {
+        let value = self.ram[address as usize];
+        self.accesses.push((address, value, "read"));
+        value
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.accesses.push((address, value, "write"));
+        self.ram[address as usize] = value;
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.ram[address as usize]
+    }
+}
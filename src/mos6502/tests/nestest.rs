@@ -0,0 +1,43 @@
+/* https://www.nesdev.org/wiki/Emulator_tests
+* Started at $C000 nestest.nes runs its CPU tests without needing a PPU, and nestest.log is
+* Nintendulator's trace of that run. Point NESTEST at a directory holding both files and
+* run `cargo test -- --ignored nestest`.
+*/
+
+use std::{env, fs, path::Path};
+
+use super::super::{Mos6502, Variant};
+use super::FlatMemory;
+
+#[test]
+#[ignore = "needs NESTEST"]
+fn nestest() {
+    let directory = env::var("NESTEST").expect("NESTEST isn't set");
+    let rom = fs::read(Path::new(&directory).join("nestest.nes")).expect("can't read nestest.nes");
+    let log = fs::read_to_string(Path::new(&directory).join("nestest.log"))
+        .expect("can't read nestest.log");
+
+    // A single 16K PRG bank after the 16 byte iNES header, mirrored at $8000 and $C000
+    let prg = &rom[16..16 + 0x4000];
+    let mut memory = FlatMemory::new();
+    memory.ram[0x8000..0xC000].copy_from_slice(prg);
+    memory.ram[0xC000..].copy_from_slice(prg);
+    // The APU and IO registers read back as $FF in the log
+    memory.ram[0x4000..0x4020].fill(0xFF);
+
+    let mut cpu = Mos6502::new(Variant::Ricoh2A03);
+    // Run the reset sequence for its 7 cycles, then jump straight into automation mode
+    cpu.run_instruction(&mut memory);
+    cpu.program_counter = 0xC000;
+    for (number, expected) in log.lines().enumerate() {
+        assert_eq!(
+            cpu.trace(&memory),
+            expected.trim_end(),
+            "line {}",
+            number + 1
+        );
+        cpu.run_instruction(&mut memory);
+    }
+    // Failed tests leave their error codes in $02 and $03
+    assert_eq!(memory.ram[0x02..0x04], [0x00, 0x00], "error codes");
+}
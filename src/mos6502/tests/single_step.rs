@@ -0,0 +1,195 @@
+/* https://github.com/SingleStepTests/65x02
+* One JSON file per opcode, each a list of single instructions run from a random state.
+* Every test has the registers and the RAM it touches before and after, and the address,
+* value and direction of the bus access made on every cycle.
+*
+* The nes6502 set matches the 2A03 and the 6502 set an NMOS 6502 with decimal mode.
+* Point SINGLE_STEP_TESTS_NES or SINGLE_STEP_TESTS_6502 at the v1 directory of a set
+* and run `cargo test -- --ignored single_step`.
+*/
+
+use std::{env, fs, path::Path};
+
+use serde_json::Value;
+
+use super::super::{
+    instruction_table::{Instruction, INSTRUCTIONS},
+    interrupts::InterruptKind,
+    Mos6502, Variant,
+};
+use super::FlatMemory;
+
+struct State {
+    pc: u16,
+    s: u8,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    ram: Vec<(u16, u8)>,
+}
+
+impl State {
+    fn parse(value: &Value) -> State {
+        let field = |name: &str| value[name].as_u64().expect("malformed CPU state");
+        State {
+            pc: field("pc") as u16,
+            s: field("s") as u8,
+            a: field("a") as u8,
+            x: field("x") as u8,
+            y: field("y") as u8,
+            p: field("p") as u8,
+            ram: value["ram"]
+                .as_array()
+                .expect("malformed RAM")
+                .iter()
+                .map(|entry| {
+                    (
+                        entry[0].as_u64().unwrap() as u16,
+                        entry[1].as_u64().unwrap() as u8,
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    fn load(&self, variant: Variant) -> (Mos6502, FlatMemory) {
+        let mut cpu = Mos6502::new(variant);
+        // Skip the power-on reset, the tests start right at an opcode fetch
+        cpu.interrupt_kind = InterruptKind::Software;
+        cpu.program_counter = self.pc;
+        cpu.stack_pointer = self.s;
+        cpu.accumulator = self.a;
+        cpu.index_x = self.x;
+        cpu.index_y = self.y;
+        cpu.set_status(self.p);
+        let mut memory = FlatMemory::new();
+        for &(address, value) in &self.ram {
+            memory.ram[address as usize] = value;
+        }
+        (cpu, memory)
+    }
+
+    fn check(&self, cpu: &Mos6502, memory: &FlatMemory) -> Result<(), String> {
+        let registers = |pc: u16, s: u8, a: u8, x: u8, y: u8, p: u8| {
+            // B and bit 5 don't exist in the CPU, only in the copy of P that gets pushed
+            format!(
+                "PC:{pc:04X} S:{s:02X} A:{a:02X} X:{x:02X} Y:{y:02X} P:{:02X}",
+                p & 0xCF
+            )
+        };
+        let expected = registers(self.pc, self.s, self.a, self.x, self.y, self.p);
+        let actual = registers(
+            cpu.program_counter,
+            cpu.stack_pointer,
+            cpu.accumulator,
+            cpu.index_x,
+            cpu.index_y,
+            cpu.status(false),
+        );
+        if actual != expected {
+            return Err(format!("registers {actual}, expected {expected}"));
+        }
+        for &(address, value) in &self.ram {
+            let actual = memory.ram[address as usize];
+            if actual != value {
+                return Err(format!(
+                    "${address:04X} is {actual:02X}, expected {value:02X}"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn run_test(variant: Variant, test: &Value) -> Result<(), String> {
+    let initial = State::parse(&test["initial"]);
+    let expected = State::parse(&test["final"]);
+    let accesses: Vec<(u16, u8, &str)> = test["cycles"]
+        .as_array()
+        .expect("malformed cycles")
+        .iter()
+        .map(|cycle| {
+            (
+                cycle[0].as_u64().unwrap() as u16,
+                cycle[1].as_u64().unwrap() as u8,
+                cycle[2].as_str().unwrap(),
+            )
+        })
+        .collect();
+
+    let (mut cpu, mut memory) = initial.load(variant);
+    while !cpu.tick(&mut memory) {}
+    expected
+        .check(&cpu, &memory)
+        .map_err(|error| format!("tick: {error}"))?;
+    if memory.accesses != accesses {
+        return Err(format!(
+            "tick: bus activity {:?}, expected {:?}",
+            memory.accesses, accesses
+        ));
+    }
+
+    let (mut cpu, mut memory) = initial.load(variant);
+    let cycles = cpu.run_instruction(&mut memory);
+    expected
+        .check(&cpu, &memory)
+        .map_err(|error| format!("run_instruction: {error}"))?;
+    if cycles != accesses.len() {
+        return Err(format!(
+            "run_instruction: took {cycles} cycles, expected {}",
+            accesses.len()
+        ));
+    }
+    Ok(())
+}
+
+// Runs every opcode file in the directory named by `variable` and prints a line per opcode
+fn run_suite(variant: Variant, variable: &str) {
+    let directory = env::var(variable).unwrap_or_else(|_| panic!("{variable} isn't set"));
+    let mut failing = Vec::new();
+    for (opcode, &instruction) in INSTRUCTIONS.iter().enumerate() {
+        // The JAM opcodes hang the CPU, there is no final state to compare
+        if instruction == Instruction::STP {
+            println!("{opcode:02x}: skipped");
+            continue;
+        }
+        let path = Path::new(&directory).join(format!("{opcode:02x}.json"));
+        let tests: Vec<Value> = match fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text).expect("malformed test file"),
+            Err(error) => {
+                println!("{opcode:02x}: can't read {}: {error}", path.display());
+                failing.push(opcode);
+                continue;
+            }
+        };
+        let mut failures = 0;
+        let mut first_failure = None;
+        for test in &tests {
+            if let Err(error) = run_test(variant, test) {
+                failures += 1;
+                first_failure.get_or_insert_with(|| format!("{}: {error}", test["name"]));
+            }
+        }
+        match first_failure {
+            None => println!("{opcode:02x}: passed {} tests", tests.len()),
+            Some(error) => {
+                println!("{opcode:02x}: failed {failures}/{}, {error}", tests.len());
+                failing.push(opcode);
+            }
+        }
+    }
+    assert!(failing.is_empty(), "failing opcodes: {failing:02x?}");
+}
+
+#[test]
+#[ignore = "needs SINGLE_STEP_TESTS_NES"]
+fn single_step_nes6502() {
+    run_suite(Variant::Ricoh2A03, "SINGLE_STEP_TESTS_NES");
+}
+
+#[test]
+#[ignore = "needs SINGLE_STEP_TESTS_6502"]
+fn single_step_6502() {
+    run_suite(Variant::Nmos6502, "SINGLE_STEP_TESTS_6502");
+}
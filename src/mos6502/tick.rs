@@ -0,0 +1,505 @@
+/* Cycle stepped core, based on https://www.nesdev.org/6502_cpu.txt
+* Every call to tick performs exactly one bus access. This includes the dummy reads
+* done while the CPU fixes up the high byte of an indexed address, and the double
+* write of read-modify-write instructions, which mappers and PPU registers can observe.
+*/
+
+use super::{
+    addressingmodes::{AddressingMode, ADDRESSING_MODES},
+    bus::Bus,
+    instruction_table::{Instruction, INSTRUCTIONS},
+    interrupts::InterruptKind,
+};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Access {
+    Read,
+    Write,
+    Modify,
+}
+
+impl super::Mos6502 {
+    // Runs a single cycle. Returns true when that cycle finished an instruction, which for
+    // one that started a DMA is once the CPU isn't halted anymore.
+    pub fn tick<B: Bus>(&mut self, bus: &mut B) -> bool {
+        if self.dma_stall != 0 {
+            self.dma_stall -= 1;
+            self.total_cycles += 1;
+            bus.tick();
+            return self.dma_stall == 0;
+        }
+        self.bus_irq = bus.irq();
+        let done = self.tick_cycle(bus);
+        self.total_cycles += 1;
+        if done && bus.take_dma() {
+            self.dma_stall = self.dma_cycles();
+        }
+        bus.tick();
+        done && self.dma_stall == 0
+    }
+
+    fn tick_cycle<B: Bus>(&mut self, bus: &mut B) -> bool {
+        if self.halted {
+            // A jammed CPU keeps $FFFF on the address bus
+            bus.read(0xFFFF);
+            return true;
+        }
+        if self.cycle == 0 {
+            if !self.start_interrupt(bus) {
+                self.opcode = self.fetch(bus);
+            }
+            self.cycle = 1;
+            self.poll_interrupts();
+            return false;
+        }
+        let instruction = INSTRUCTIONS[self.opcode as usize];
+        let done = match instruction {
+            Instruction::BCC => self.tick_branch(bus, !self.carry),
+            Instruction::BCS => self.tick_branch(bus, self.carry),
+            Instruction::BEQ => self.tick_branch(bus, self.zero),
+            Instruction::BMI => self.tick_branch(bus, self.sign),
+            Instruction::BNE => self.tick_branch(bus, !self.zero),
+            Instruction::BPL => self.tick_branch(bus, !self.sign),
+            Instruction::BVC => self.tick_branch(bus, !self.overflow),
+            Instruction::BVS => self.tick_branch(bus, self.overflow),
+            Instruction::BRK => self.tick_brk(bus),
+            Instruction::JMP => self.tick_jmp(bus),
+            Instruction::JSR => self.tick_jsr(bus),
+            Instruction::RTI => self.tick_rti(bus),
+            Instruction::RTS => self.tick_rts(bus),
+            Instruction::PHA | Instruction::PHP => self.tick_push(bus, instruction),
+            Instruction::PLA | Instruction::PLP => self.tick_pull(bus, instruction),
+            Instruction::STP => {
+                bus.read(self.program_counter);
+                self.halted = true;
+                true
+            }
+            _ => self.tick_addressing(bus, instruction),
+        };
+        self.cycle = if done { 0 } else { self.cycle + 1 };
+        self.poll_interrupts();
+        done
+    }
+
+    fn fetch<B: Bus>(&mut self, bus: &mut B) -> u8 {
+        let value = bus.read(self.program_counter);
+        self.program_counter = self.program_counter.wrapping_add(1);
+        value
+    }
+
+    fn tick_addressing<B: Bus>(&mut self, bus: &mut B, instruction: Instruction) -> bool {
+        let access = if instruction.rwr() {
+            Access::Modify
+        } else if instruction.store() {
+            Access::Write
+        } else {
+            Access::Read
+        };
+        match (ADDRESSING_MODES[self.opcode as usize], self.cycle) {
+            (AddressingMode::Implied, _) => {
+                bus.read(self.program_counter);
+                self.execute_implied(instruction);
+                true
+            }
+            (AddressingMode::Accumulator, _) => {
+                bus.read(self.program_counter);
+                self.accumulator = self.execute_modify(instruction, self.accumulator);
+                true
+            }
+            (AddressingMode::Immediate, _) => {
+                let value = self.fetch(bus);
+                self.execute_read(instruction, value);
+                true
+            }
+            (AddressingMode::ZeroPage, 1) => {
+                self.address = self.fetch(bus) as u16;
+                false
+            }
+            (AddressingMode::ZeroPage, cycle) => {
+                self.tick_access(bus, instruction, access, cycle - 2)
+            }
+            (AddressingMode::ZeroPageX | AddressingMode::ZeroPageY, 1) => {
+                self.address = self.fetch(bus) as u16;
+                false
+            }
+            (AddressingMode::ZeroPageX, 2) => {
+                bus.read(self.address);
+                self.address = (self.address as u8).wrapping_add(self.index_x) as u16;
+                false
+            }
+            (AddressingMode::ZeroPageY, 2) => {
+                bus.read(self.address);
+                self.address = (self.address as u8).wrapping_add(self.index_y) as u16;
+                false
+            }
+            (AddressingMode::ZeroPageX | AddressingMode::ZeroPageY, cycle) => {
+                self.tick_access(bus, instruction, access, cycle - 3)
+            }
+            (AddressingMode::Absolute, 1) => {
+                self.address = self.fetch(bus) as u16;
+                false
+            }
+            (AddressingMode::Absolute, 2) => {
+                self.address |= (self.fetch(bus) as u16) << 8;
+                false
+            }
+            (AddressingMode::Absolute, cycle) => {
+                self.tick_access(bus, instruction, access, cycle - 3)
+            }
+            (AddressingMode::AbsoluteX | AddressingMode::AbsoluteY, 1) => {
+                self.address = self.fetch(bus) as u16;
+                false
+            }
+            (AddressingMode::AbsoluteX, 2) => {
+                let high = self.fetch(bus);
+                self.add_index(high, self.index_x);
+                false
+            }
+            (AddressingMode::AbsoluteY, 2) => {
+                let high = self.fetch(bus);
+                self.add_index(high, self.index_y);
+                false
+            }
+            (AddressingMode::AbsoluteX | AddressingMode::AbsoluteY, 3) => {
+                self.tick_fix_high_byte(bus, instruction, access)
+            }
+            (AddressingMode::AbsoluteX | AddressingMode::AbsoluteY, cycle) => {
+                self.tick_access(bus, instruction, access, cycle - 4)
+            }
+            (AddressingMode::ZeroPageIndexedIndirectX, 1) => {
+                self.pointer = self.fetch(bus);
+                false
+            }
+            (AddressingMode::ZeroPageIndexedIndirectX, 2) => {
+                bus.read(self.pointer as u16);
+                self.pointer = self.pointer.wrapping_add(self.index_x);
+                false
+            }
+            (AddressingMode::ZeroPageIndexedIndirectX, 3) => {
+                self.address = bus.read(self.pointer as u16) as u16;
+                false
+            }
+            (AddressingMode::ZeroPageIndexedIndirectX, 4) => {
+                let high = bus.read(self.pointer.wrapping_add(1) as u16);
+                self.address |= (high as u16) << 8;
+                false
+            }
+            (AddressingMode::ZeroPageIndexedIndirectX, cycle) => {
+                self.tick_access(bus, instruction, access, cycle - 5)
+            }
+            (AddressingMode::ZeroPageIndirectIndexedY, 1) => {
+                self.pointer = self.fetch(bus);
+                false
+            }
+            (AddressingMode::ZeroPageIndirectIndexedY, 2) => {
+                self.address = bus.read(self.pointer as u16) as u16;
+                false
+            }
+            (AddressingMode::ZeroPageIndirectIndexedY, 3) => {
+                let high = bus.read(self.pointer.wrapping_add(1) as u16);
+                self.add_index(high, self.index_y);
+                false
+            }
+            (AddressingMode::ZeroPageIndirectIndexedY, 4) => {
+                self.tick_fix_high_byte(bus, instruction, access)
+            }
+            (AddressingMode::ZeroPageIndirectIndexedY, cycle) => {
+                self.tick_access(bus, instruction, access, cycle - 5)
+            }
+            (AddressingMode::Relative | AddressingMode::AbsoluteIndirect, _) => {
+                panic!("Addressing mode is only used by control flow instructions")
+            }
+        }
+    }
+
+    // Combines the high byte with the low byte in `address` plus the index. The carry out of
+    // the low byte is only applied on the next cycle, so the address is left unfixed.
+    fn add_index(&mut self, high: u8, index: u8) {
+        let (low, page_crossed) = (self.address as u8).overflowing_add(index);
+        self.address = u16::from_be_bytes([high, low]);
+        self.page_crossed = page_crossed;
+    }
+
+    // The cycle where the indexed address is read before its high byte has been fixed.
+    // Reads that did not cross a page are done here, everything else reads again.
+    fn tick_fix_high_byte<B: Bus>(
+        &mut self,
+        bus: &mut B,
+        instruction: Instruction,
+        access: Access,
+    ) -> bool {
+        let value = bus.read(self.address);
+        if self.page_crossed {
+            self.address = self.address.wrapping_add(0x100);
+            false
+        } else if access == Access::Read {
+            self.execute_read(instruction, value);
+            true
+        } else {
+            false
+        }
+    }
+
+    // The cycles after the effective address is known, `step` counts from zero
+    fn tick_access<B: Bus>(
+        &mut self,
+        bus: &mut B,
+        instruction: Instruction,
+        access: Access,
+        step: u8,
+    ) -> bool {
+        match (access, step) {
+            (Access::Read, _) => {
+                let value = bus.read(self.address);
+                self.execute_read(instruction, value);
+                true
+            }
+            (Access::Write, _) => {
+                let (address, value) = self.execute_store(instruction, self.address);
+                bus.write(address, value);
+                true
+            }
+            (Access::Modify, 0) => {
+                self.data = bus.read(self.address);
+                false
+            }
+            // The unmodified value is written back while the ALU works on it
+            (Access::Modify, 1) => {
+                bus.write(self.address, self.data);
+                self.data = self.execute_modify(instruction, self.data);
+                false
+            }
+            (Access::Modify, _) => {
+                bus.write(self.address, self.data);
+                true
+            }
+        }
+    }
+
+    fn tick_branch<B: Bus>(&mut self, bus: &mut B, condition: bool) -> bool {
+        match self.cycle {
+            1 => {
+                self.data = self.fetch(bus);
+                !condition
+            }
+            2 => {
+                // A taken branch doesn't poll for interrupts before this cycle, so an IRQ
+                // that just arrived waits for the next instruction
+                if self.run_irq && !self.prev_run_irq {
+                    self.run_irq = false;
+                }
+                // The opcode after the branch is read while the low byte is added
+                bus.read(self.program_counter);
+                let target = self.program_counter.wrapping_add(self.data as i8 as u16);
+                let crossed = target & 0xFF00 != self.program_counter & 0xFF00;
+                self.program_counter = (self.program_counter & 0xFF00) | (target & 0x00FF);
+                self.address = target;
+                !crossed
+            }
+            _ => {
+                bus.read(self.program_counter);
+                self.program_counter = self.address;
+                true
+            }
+        }
+    }
+
+    fn tick_jmp<B: Bus>(&mut self, bus: &mut B) -> bool {
+        let indirect = matches!(
+            ADDRESSING_MODES[self.opcode as usize],
+            AddressingMode::AbsoluteIndirect
+        );
+        match self.cycle {
+            1 => {
+                self.address = self.fetch(bus) as u16;
+                false
+            }
+            2 => {
+                let high = self.fetch(bus);
+                self.address |= (high as u16) << 8;
+                if !indirect {
+                    self.program_counter = self.address;
+                }
+                !indirect
+            }
+            3 => {
+                self.data = bus.read(self.address);
+                false
+            }
+            _ => {
+                // The pointer's high byte is fetched without carrying into the page
+                let address = (self.address & 0xFF00) | (self.address.wrapping_add(1) & 0x00FF);
+                let high = bus.read(address);
+                self.program_counter = u16::from_be_bytes([high, self.data]);
+                true
+            }
+        }
+    }
+
+    fn tick_jsr<B: Bus>(&mut self, bus: &mut B) -> bool {
+        match self.cycle {
+            1 => {
+                self.data = self.fetch(bus);
+                false
+            }
+            2 => {
+                bus.read(0x0100 | self.stack_pointer as u16);
+                false
+            }
+            3 => {
+                self.push(bus, (self.program_counter >> 8) as u8);
+                false
+            }
+            4 => {
+                self.push(bus, self.program_counter as u8);
+                false
+            }
+            _ => {
+                let high = bus.read(self.program_counter);
+                self.program_counter = u16::from_be_bytes([high, self.data]);
+                true
+            }
+        }
+    }
+
+    fn tick_rts<B: Bus>(&mut self, bus: &mut B) -> bool {
+        match self.cycle {
+            1 => {
+                bus.read(self.program_counter);
+                false
+            }
+            2 => {
+                bus.read(0x0100 | self.stack_pointer as u16);
+                false
+            }
+            3 => {
+                self.program_counter = self.pull(bus) as u16;
+                false
+            }
+            4 => {
+                self.program_counter |= (self.pull(bus) as u16) << 8;
+                false
+            }
+            _ => {
+                self.fetch(bus);
+                true
+            }
+        }
+    }
+
+    fn tick_rti<B: Bus>(&mut self, bus: &mut B) -> bool {
+        match self.cycle {
+            1 => {
+                bus.read(self.program_counter);
+                false
+            }
+            2 => {
+                bus.read(0x0100 | self.stack_pointer as u16);
+                false
+            }
+            3 => {
+                let status = self.pull(bus);
+                self.set_status(status);
+                false
+            }
+            4 => {
+                self.program_counter = self.pull(bus) as u16;
+                false
+            }
+            _ => {
+                self.program_counter |= (self.pull(bus) as u16) << 8;
+                true
+            }
+        }
+    }
+
+    // BRK, IRQ, NMI and reset all share this sequence
+    fn tick_brk<B: Bus>(&mut self, bus: &mut B) -> bool {
+        match self.cycle {
+            1 => {
+                if self.interrupt_kind == InterruptKind::Software {
+                    // Padding byte
+                    self.fetch(bus);
+                } else {
+                    bus.read(self.program_counter);
+                }
+                false
+            }
+            2 => {
+                self.interrupt_push(bus, (self.program_counter >> 8) as u8);
+                false
+            }
+            3 => {
+                self.interrupt_push(bus, self.program_counter as u8);
+                false
+            }
+            4 => {
+                let brk = self.interrupt_kind == InterruptKind::Software;
+                self.interrupt_push(bus, self.status(brk));
+                self.address = self.interrupt_vector();
+                false
+            }
+            5 => {
+                self.data = bus.read(self.address);
+                self.interrupt_enable = false;
+                false
+            }
+            _ => {
+                let high = bus.read(self.address.wrapping_add(1));
+                self.program_counter = u16::from_be_bytes([high, self.data]);
+                self.interrupt_kind = InterruptKind::Software;
+                true
+            }
+        }
+    }
+
+    // Reset turns the pushes into reads but still moves the stack pointer
+    fn interrupt_push<B: Bus>(&mut self, bus: &mut B, value: u8) {
+        if self.interrupt_kind == InterruptKind::Reset {
+            bus.read(0x0100 | self.stack_pointer as u16);
+            self.stack_pointer = self.stack_pointer.wrapping_sub(1);
+        } else {
+            self.push(bus, value);
+        }
+    }
+
+    fn tick_push<B: Bus>(&mut self, bus: &mut B, instruction: Instruction) -> bool {
+        match self.cycle {
+            1 => {
+                bus.read(self.program_counter);
+                false
+            }
+            _ => {
+                let value = if instruction == Instruction::PHA {
+                    self.accumulator
+                } else {
+                    self.status(true)
+                };
+                self.push(bus, value);
+                true
+            }
+        }
+    }
+
+    fn tick_pull<B: Bus>(&mut self, bus: &mut B, instruction: Instruction) -> bool {
+        match self.cycle {
+            1 => {
+                bus.read(self.program_counter);
+                false
+            }
+            2 => {
+                bus.read(0x0100 | self.stack_pointer as u16);
+                false
+            }
+            _ => {
+                let value = self.pull(bus);
+                if instruction == Instruction::PLA {
+                    self.lda(value);
+                } else {
+                    self.set_status(value);
+                }
+                true
+            }
+        }
+    }
+}
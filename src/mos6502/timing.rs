@@ -1,13 +1,17 @@
 use super::{addressingmodes::AddressingMode, instruction_table::Instruction};
 
-fn get_timing(mode: AddressingMode, instruction: Instruction, crossed_page: bool) -> usize {
+// Base number of cycles an instruction takes. The extra cycles of taken branches are added
+// by the branch itself since they depend on the flags.
+pub fn get_timing(mode: AddressingMode, instruction: Instruction, crossed_page: bool) -> usize {
     match mode {
         AddressingMode::Implied => match instruction {
             Instruction::BRK => 7,
             Instruction::RTI | Instruction::RTS => 6,
+            Instruction::PHA | Instruction::PHP => 3,
+            Instruction::PLA | Instruction::PLP => 4,
             _ => 2,
         },
-        AddressingMode::Immediate => 2,
+        AddressingMode::Accumulator | AddressingMode::Immediate => 2,
         AddressingMode::ZeroPage => {
             if instruction.rwr() {
                 5
@@ -36,52 +40,68 @@ fn get_timing(mode: AddressingMode, instruction: Instruction, crossed_page: bool
                 }
             }
         },
-        AddressingMode::AbsoluteX => {
-            if instruction == Instruction::STA {
-                5
-            } else if instruction.rwr() {
+        // Stores and read-modify-write instructions always spend the cycle used to fix the
+        // high byte of the address, reads only when a page is actually crossed
+        AddressingMode::AbsoluteX | AddressingMode::AbsoluteY => {
+            if instruction.rwr() {
                 7
-            } else if crossed_page {
+            } else if instruction.store() || crossed_page {
                 5
             } else {
                 4
             }
         }
-        AddressingMode::AbsoluteY => {
-            if instruction == Instruction::STA {
-                5
-            } else if crossed_page {
-                5
+        AddressingMode::AbsoluteIndirect => 5,
+        AddressingMode::ZeroPageIndexedIndirectX => {
+            if instruction.rwr() {
+                8
             } else {
-                4
+                6
             }
         }
-        AddressingMode::AbsoluteIndirect => 5,
-        AddressingMode::ZeroPageIndexedIndirectX => 6,
         AddressingMode::ZeroPageIndirectIndexedY => {
-            if instruction == Instruction::STA {
-                6
-            } else if crossed_page {
+            if instruction.rwr() {
+                8
+            } else if instruction.store() || crossed_page {
                 6
             } else {
                 5
             }
         }
-        AddressingMode::Relative => {
-            if crossed_page {
-                3
-            } else {
-                2
-            }
-        }
+        AddressingMode::Relative => 2,
     }
 }
 
 impl Instruction {
-    fn rwr(&self) -> bool {
-        match self {
-            Self::ASL | Self::DEC | Self::INC | Self::LSR | Self::ROL | Self::ROR => true,
-            _ => false,
-        }
+    pub fn rwr(&self) -> bool {
+        matches!(
+            self,
+            Self::ASL
+                | Self::DEC
+                | Self::INC
+                | Self::LSR
+                | Self::ROL
+                | Self::ROR
+                | Self::SLO
+                | Self::RLA
+                | Self::SRE
+                | Self::RRA
+                | Self::DCP
+                | Self::ISC
+        )
+    }
+
+    pub fn store(&self) -> bool {
+        matches!(
+            self,
+            Self::STA
+                | Self::STX
+                | Self::STY
+                | Self::SAX
+                | Self::SHX
+                | Self::SHY
+                | Self::AHX
+                | Self::TAS
+        )
     }
 }
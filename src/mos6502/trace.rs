@@ -0,0 +1,124 @@
+/* Trace lines in the format Nintendulator logs, which is the format of nestest.log
+* https://www.qmtpro.com/~nes/misc/nestest.log
+*
+* C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+*
+* Unofficial opcodes are marked with a * before the mnemonic. Operands show the address
+* they resolve to and the value there before the instruction runs.
+*/
+
+use super::{
+    addressingmodes::AddressingMode,
+    bus::Bus,
+    disassembler::{disassemble, Disassembly},
+    instruction_table::Instruction,
+};
+
+impl super::Mos6502 {
+    // Describes the instruction at the program counter, call it before the instruction runs
+    pub fn trace<B: Bus>(&self, bus: &B) -> String {
+        let disassembly = disassemble(bus, self.program_counter);
+        let bytes: Vec<String> = disassembly
+            .bytes
+            .iter()
+            .map(|byte| format!("{byte:02X}"))
+            .collect();
+        let marker = if disassembly.unofficial { '*' } else { ' ' };
+        let mnemonic = match disassembly.instruction {
+            Instruction::ISC => "ISB".to_owned(),
+            _ => disassembly.mnemonic(),
+        };
+        let operand = self.trace_operand(bus, &disassembly);
+        let text = if operand.is_empty() {
+            mnemonic
+        } else {
+            format!("{mnemonic} {operand}")
+        };
+        // There's no PPU to ask yet, it runs 3 dots per CPU cycle from the top left at power on
+        let dot = self.total_cycles * 3;
+        format!(
+            "{:04X}  {:<8} {}{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+            self.program_counter,
+            bytes.join(" "),
+            marker,
+            text,
+            self.accumulator,
+            self.index_x,
+            self.index_y,
+            self.status(false),
+            self.stack_pointer,
+            dot / 341 % 262,
+            dot % 341,
+            self.total_cycles
+        )
+    }
+
+    // The operand as the disassembler writes it, followed by the address it resolves to
+    // and what's there
+    fn trace_operand<B: Bus>(&self, bus: &B, disassembly: &Disassembly) -> String {
+        let operand = disassembly.operand();
+        let byte = disassembly.bytes.get(1).copied().unwrap_or(0);
+        let word = u16::from_be_bytes([disassembly.bytes.get(2).copied().unwrap_or(0), byte]);
+        let zero_page_word = |pointer: u8| {
+            u16::from_be_bytes([
+                bus.peek(pointer.wrapping_add(1) as u16),
+                bus.peek(pointer as u16),
+            ])
+        };
+        match disassembly.mode {
+            AddressingMode::Implied
+            | AddressingMode::Accumulator
+            | AddressingMode::Immediate
+            | AddressingMode::Relative => operand,
+            AddressingMode::ZeroPage => format!("{operand} = {:02X}", bus.peek(byte as u16)),
+            AddressingMode::ZeroPageX => {
+                let address = byte.wrapping_add(self.index_x);
+                format!(
+                    "{operand} @ {address:02X} = {:02X}",
+                    bus.peek(address as u16)
+                )
+            }
+            AddressingMode::ZeroPageY => {
+                let address = byte.wrapping_add(self.index_y);
+                format!(
+                    "{operand} @ {address:02X} = {:02X}",
+                    bus.peek(address as u16)
+                )
+            }
+            AddressingMode::Absolute => match disassembly.instruction {
+                Instruction::JMP | Instruction::JSR => operand,
+                _ => format!("{operand} = {:02X}", bus.peek(word)),
+            },
+            AddressingMode::AbsoluteX => {
+                let address = word.wrapping_add(self.index_x as u16);
+                format!("{operand} @ {address:04X} = {:02X}", bus.peek(address))
+            }
+            AddressingMode::AbsoluteY => {
+                let address = word.wrapping_add(self.index_y as u16);
+                format!("{operand} @ {address:04X} = {:02X}", bus.peek(address))
+            }
+            AddressingMode::AbsoluteIndirect => {
+                let low = bus.peek(word);
+                let high = bus.peek((word & 0xFF00) | (word.wrapping_add(1) & 0x00FF));
+                let target = u16::from_be_bytes([high, low]);
+                format!("{operand} = {target:04X}")
+            }
+            AddressingMode::ZeroPageIndexedIndirectX => {
+                let pointer = byte.wrapping_add(self.index_x);
+                let address = zero_page_word(pointer);
+                format!(
+                    "{operand} @ {pointer:02X} = {address:04X} = {:02X}",
+                    bus.peek(address)
+                )
+            }
+            AddressingMode::ZeroPageIndirectIndexedY => {
+                let base = zero_page_word(byte);
+                let address = base.wrapping_add(self.index_y as u16);
+                format!(
+                    "{operand} = {base:04X} @ {address:04X} = {:02X}",
+                    bus.peek(address)
+                )
+            }
+        }
+    }
+}
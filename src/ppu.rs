@@ -0,0 +1,424 @@
+/* https://www.nesdev.org/wiki/PPU_registers
+* Only the register file so far, nothing is rendered yet.
+*
+* $2000 PPUCTRL    VPHBSINN NMI enable, sprite size, pattern tables, VRAM increment, nametable
+* $2001 PPUMASK    BGRsbMmG colour emphasis, sprite and background enable, greyscale
+* $2002 PPUSTATUS  VSO..... vblank, sprite 0 hit, sprite overflow
+* $2003 OAMADDR
+* $2004 OAMDATA    increments OAMADDR on writes
+* $2005 PPUSCROLL  X then Y
+* $2006 PPUADDR    high then low byte
+* $2007 PPUDATA    increments the VRAM address by 1 or 32
+*
+* PPUSCROLL and PPUADDR share a write toggle, which reading PPUSTATUS resets. Both go
+* through the temporary VRAM address t, PPUADDR copies it to the VRAM address v on its
+* second write.
+* https://www.nesdev.org/wiki/PPU_scrolling#PPU_internal_registers
+*
+* Reading PPUDATA returns what was in a buffer, which then gets refilled from the new
+* address. Palette reads skip the buffer, but still refill it from the nametable under
+* the palette.
+*
+* The registers sit on their own data bus with a latch that holds the last value written
+* to or read from any of them. Write only registers read back the latch, as do the bits
+* PPUSTATUS doesn't drive. Each bit of the latch decays to 0 if it isn't refreshed for
+* about 600ms.
+* https://www.nesdev.org/wiki/Open_bus_behavior#PPU_open_bus
+*/
+
+use crate::cartridge::{Mapper, Mirroring};
+
+// About 600ms of NTSC CPU cycles
+const LATCH_DECAY: u64 = 1_073_864;
+
+pub struct Ppu {
+    ctrl: u8,
+    mask: u8,
+    vblank: bool,
+    sprite_zero_hit: bool,
+    sprite_overflow: bool,
+    oam_address: u8,
+    oam: [u8; 256],
+    // Current and temporary VRAM address, fine X scroll and the shared write toggle
+    v: u16,
+    t: u16,
+    fine_x: u8,
+    write_toggle: bool,
+    read_buffer: u8,
+    // The console only has 2K, the other two nametables are for four screen boards
+    nametables: [u8; 0x1000],
+    palette: [u8; 32],
+    io_latch: u8,
+    // When each bit of the latch was last refreshed with a 1
+    latch_refreshed: [u64; 8],
+    // CPU cycles since power on
+    cycles: u64,
+}
+
+impl Ppu {
+    pub fn new() -> Self {
+        Ppu {
+            ctrl: 0,
+            mask: 0,
+            vblank: false,
+            sprite_zero_hit: false,
+            sprite_overflow: false,
+            oam_address: 0,
+            oam: [0; 256],
+            v: 0,
+            t: 0,
+            fine_x: 0,
+            write_toggle: false,
+            read_buffer: 0,
+            nametables: [0; 0x1000],
+            palette: [0; 32],
+            io_latch: 0,
+            latch_refreshed: [0; 8],
+            cycles: 0,
+        }
+    }
+
+    // $2000-$3FFF, mirrored every 8 bytes
+    pub fn read(&mut self, address: u16, cartridge: &mut Option<Box<dyn Mapper>>) -> u8 {
+        match address % 8 {
+            2 => {
+                let status = self.peek_status();
+                self.vblank = false;
+                self.write_toggle = false;
+                self.refresh_latch(status, 0xE0);
+                status
+            }
+            4 => {
+                let value = self.peek_oam();
+                self.refresh_latch(value, 0xFF);
+                value
+            }
+            7 => {
+                let address = self.v & 0x3FFF;
+                let value = if address >= 0x3F00 {
+                    let value = self.read_palette(address);
+                    self.refresh_latch(value, 0x3F);
+                    // The buffer gets the nametable byte the palette is drawn over
+                    self.read_buffer = self.read_memory(address - 0x1000, cartridge);
+                    self.latch() & 0xC0 | value
+                } else {
+                    let value = self.read_buffer;
+                    self.read_buffer = self.read_memory(address, cartridge);
+                    self.refresh_latch(value, 0xFF);
+                    value
+                };
+                self.increment_v(cartridge);
+                value
+            }
+            _ => self.latch(),
+        }
+    }
+
+    // Same as read but without any side effects, for debuggers
+    pub fn peek(&self, address: u16) -> u8 {
+        match address % 8 {
+            2 => self.peek_status(),
+            4 => self.peek_oam(),
+            7 => {
+                let address = self.v & 0x3FFF;
+                if address >= 0x3F00 {
+                    self.latch() & 0xC0 | self.read_palette(address)
+                } else {
+                    self.read_buffer
+                }
+            }
+            _ => self.latch(),
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8, cartridge: &mut Option<Box<dyn Mapper>>) {
+        self.refresh_latch(value, 0xFF);
+        if let Some(cartridge) = cartridge {
+            cartridge.ppu_register_write(address, value);
+        }
+        match address % 8 {
+            0 => {
+                self.ctrl = value;
+                self.t = (self.t & !0x0C00) | ((value & 0x03) as u16) << 10;
+            }
+            1 => self.mask = value,
+            3 => self.oam_address = value,
+            4 => {
+                self.oam[self.oam_address as usize] = value;
+                self.oam_address = self.oam_address.wrapping_add(1);
+            }
+            5 => {
+                if self.write_toggle {
+                    // Fine Y and coarse Y
+                    self.t = (self.t & !0x73E0)
+                        | ((value & 0x07) as u16) << 12
+                        | ((value & 0xF8) as u16) << 2;
+                } else {
+                    self.t = (self.t & !0x001F) | (value >> 3) as u16;
+                    self.fine_x = value & 0x07;
+                }
+                self.write_toggle = !self.write_toggle;
+            }
+            6 => {
+                if self.write_toggle {
+                    self.t = (self.t & 0xFF00) | value as u16;
+                    self.v = self.t;
+                    if let Some(cartridge) = cartridge {
+                        cartridge.ppu_address_bus(self.v);
+                    }
+                } else {
+                    // The top bit of the 15 bit register gets cleared
+                    self.t = (self.t & 0x00FF) | ((value & 0x3F) as u16) << 8;
+                }
+                self.write_toggle = !self.write_toggle;
+            }
+            7 => {
+                self.write_memory(self.v & 0x3FFF, value, cartridge);
+                self.increment_v(cartridge);
+            }
+            // PPUSTATUS is read only
+            _ => (),
+        }
+    }
+
+    // Called once every CPU cycle
+    pub fn tick(&mut self) {
+        self.cycles += 1;
+    }
+
+    // Whether the PPU is pulling the CPU's NMI line, for whatever drives the CPU to pass on
+    // to set_nmi_line
+    pub fn nmi(&self) -> bool {
+        self.vblank && self.ctrl & 0x80 != 0
+    }
+
+    // For the renderer to call at the start of scanline 241
+    pub fn start_vblank(&mut self) {
+        self.vblank = true;
+    }
+
+    // For the renderer to call at the start of the pre-render scanline
+    pub fn end_vblank(&mut self) {
+        self.vblank = false;
+        self.sprite_zero_hit = false;
+        self.sprite_overflow = false;
+    }
+
+    fn peek_status(&self) -> u8 {
+        (self.vblank as u8) << 7
+            | (self.sprite_zero_hit as u8) << 6
+            | (self.sprite_overflow as u8) << 5
+            | self.latch() & 0x1F
+    }
+
+    fn peek_oam(&self) -> u8 {
+        let value = self.oam[self.oam_address as usize];
+        // Bits 2-4 of the sprite attribute byte don't exist
+        if self.oam_address % 4 == 2 {
+            value & 0xE3
+        } else {
+            value
+        }
+    }
+
+    // The latch with the bits that have decayed cleared
+    fn latch(&self) -> u8 {
+        (0..8)
+            .filter(|&bit| self.io_latch & (1 << bit) != 0)
+            .filter(|&bit| self.cycles - self.latch_refreshed[bit] < LATCH_DECAY)
+            .fold(0, |latch, bit| latch | (1 << bit))
+    }
+
+    // Puts the bits of value picked by mask on the latch
+    fn refresh_latch(&mut self, value: u8, mask: u8) {
+        self.io_latch = (self.latch() & !mask) | (value & mask);
+        for bit in 0..8 {
+            if value & mask & (1 << bit) != 0 {
+                self.latch_refreshed[bit] = self.cycles;
+            }
+        }
+    }
+
+    fn increment_v(&mut self, cartridge: &mut Option<Box<dyn Mapper>>) {
+        let increment = if self.ctrl & 0x04 != 0 { 32 } else { 1 };
+        self.v = (self.v + increment) & 0x7FFF;
+        if let Some(cartridge) = cartridge {
+            cartridge.ppu_address_bus(self.v);
+        }
+    }
+
+    fn nametable_index(&self, address: u16, cartridge: &Option<Box<dyn Mapper>>) -> usize {
+        let mirroring = cartridge
+            .as_ref()
+            .map_or(Mirroring::Horizontal, |cartridge| cartridge.mirroring());
+        mirroring.page(address) * 0x400 + (address & 0x3FF) as usize
+    }
+
+    // Palette entries $3F10, $3F14, $3F18 and $3F1C are the same as the ones $10 below
+    fn palette_index(address: u16) -> usize {
+        let index = (address & 0x1F) as usize;
+        if index & 0x03 == 0 {
+            index & 0x0F
+        } else {
+            index
+        }
+    }
+
+    fn read_palette(&self, address: u16) -> u8 {
+        let value = self.palette[Ppu::palette_index(address)];
+        // Greyscale drops the hue
+        if self.mask & 0x01 != 0 {
+            value & 0x30
+        } else {
+            value
+        }
+    }
+
+    // $0000-$3EFF, everything but the palette
+    fn read_memory(&mut self, address: u16, cartridge: &mut Option<Box<dyn Mapper>>) -> u8 {
+        match address {
+            0x0000..=0x1FFF => cartridge
+                .as_mut()
+                .map_or(0, |cartridge| cartridge.ppu_read(address)),
+            _ => {
+                // $3000-$3EFF mirrors $2000-$2EFF
+                let address = 0x2000 | (address & 0x0FFF);
+                let answered = cartridge
+                    .as_mut()
+                    .and_then(|cartridge| cartridge.nametable_read(address));
+                answered
+                    .unwrap_or_else(|| self.nametables[self.nametable_index(address, cartridge)])
+            }
+        }
+    }
+
+    fn write_memory(&mut self, address: u16, value: u8, cartridge: &mut Option<Box<dyn Mapper>>) {
+        match address {
+            0x0000..=0x1FFF => {
+                if let Some(cartridge) = cartridge {
+                    cartridge.ppu_write(address, value);
+                }
+            }
+            0x2000..=0x3EFF => {
+                let address = 0x2000 | (address & 0x0FFF);
+                let answered = cartridge
+                    .as_mut()
+                    .is_some_and(|cartridge| cartridge.nametable_write(address, value));
+                if !answered {
+                    let index = self.nametable_index(address, cartridge);
+                    self.nametables[index] = value;
+                }
+            }
+            _ => self.palette[Ppu::palette_index(address)] = value & 0x3F,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Ppu, LATCH_DECAY};
+
+    // Points v at address through PPUADDR
+    fn set_address(ppu: &mut Ppu, address: u16) {
+        let [high, low] = address.to_be_bytes();
+        ppu.write(0x2006, high, &mut None);
+        ppu.write(0x2006, low, &mut None);
+    }
+
+    #[test]
+    fn status_read() {
+        let mut ppu = Ppu::new();
+        ppu.start_vblank();
+        ppu.write(0x2006, 0x21, &mut None);
+        // The latch fills the low bits, and peeking changes nothing
+        assert_eq!(ppu.peek(0x2002), 0x80 | 0x01);
+        assert_eq!(ppu.read(0x2002, &mut None), 0x80 | 0x01);
+        assert_eq!(ppu.read(0x2002, &mut None), 0x01);
+        // The write toggle was reset, so this is the high byte again
+        set_address(&mut ppu, 0x2345);
+        assert_eq!(ppu.v, 0x2345);
+        ppu.write(0x2006, 0x21, &mut None);
+        ppu.read(0x2002, &mut None);
+        ppu.write(0x2005, 0xFF, &mut None);
+        assert_eq!((ppu.fine_x, ppu.write_toggle), (0x07, true));
+    }
+
+    #[test]
+    fn buffered_read() {
+        let mut ppu = Ppu::new();
+        set_address(&mut ppu, 0x2000);
+        for value in [0x11, 0x22, 0x33] {
+            ppu.write(0x2007, value, &mut None);
+        }
+        set_address(&mut ppu, 0x2000);
+        // The first read returns the stale buffer, then everything is one read behind
+        let values = [(); 4].map(|_| ppu.read(0x2007, &mut None));
+        assert_eq!(values, [0x00, 0x11, 0x22, 0x33]);
+        // With the increment at 32, across the nametable
+        set_address(&mut ppu, 0x2001);
+        ppu.write(0x2000, 0x04, &mut None);
+        ppu.read(0x2007, &mut None);
+        assert_eq!(ppu.v, 0x2021);
+        // $3000-$3EFF mirrors the nametables
+        set_address(&mut ppu, 0x3002);
+        ppu.read(0x2007, &mut None);
+        assert_eq!(ppu.read(0x2007, &mut None), 0x33);
+    }
+
+    // Palette reads come straight back, and the buffer picks up the nametable under them
+    #[test]
+    fn palette_read() {
+        let mut ppu = Ppu::new();
+        set_address(&mut ppu, 0x2F05);
+        ppu.write(0x2007, 0x5A, &mut None);
+        set_address(&mut ppu, 0x3F05);
+        ppu.write(0x2007, 0x2C, &mut None);
+        set_address(&mut ppu, 0x3F05);
+        assert_eq!(ppu.read(0x2007, &mut None) & 0x3F, 0x2C);
+        assert_eq!(ppu.read_buffer, 0x5A);
+        // The top two bits are the latch
+        ppu.write(0x2003, 0xC0, &mut None);
+        set_address(&mut ppu, 0x3F05);
+        ppu.write(0x2003, 0xC0, &mut None);
+        assert_eq!(ppu.read(0x2007, &mut None), 0xC0 | 0x2C);
+    }
+
+    #[test]
+    fn palette_mirroring() {
+        let mut ppu = Ppu::new();
+        for (address, value) in [(0x3F00, 0x01), (0x3F14, 0x02), (0x3F11, 0x03)] {
+            set_address(&mut ppu, address);
+            ppu.write(0x2007, value, &mut None);
+        }
+        let read = |ppu: &mut Ppu, address| {
+            set_address(ppu, address);
+            ppu.read(0x2007, &mut None) & 0x3F
+        };
+        // The backdrop colours of the sprite palettes are the background ones
+        assert_eq!(read(&mut ppu, 0x3F10), 0x01);
+        assert_eq!(read(&mut ppu, 0x3F04), 0x02);
+        // The others are their own
+        assert_eq!(read(&mut ppu, 0x3F01), 0x00);
+        assert_eq!(read(&mut ppu, 0x3F11), 0x03);
+        // And the whole palette repeats up to $3FFF
+        assert_eq!(read(&mut ppu, 0x3FF0), 0x01);
+    }
+
+    #[test]
+    fn latch_decay() {
+        let mut ppu = Ppu::new();
+        ppu.write(0x2000, 0xF0, &mut None);
+        assert_eq!(ppu.read(0x2001, &mut None), 0xF0);
+        for _ in 0..LATCH_DECAY - 1 {
+            ppu.tick();
+        }
+        // Refreshing the low bits keeps only them going
+        ppu.write(0x2000, 0x0F, &mut None);
+        ppu.tick();
+        assert_eq!(ppu.read(0x2005, &mut None), 0x0F);
+        for _ in 0..LATCH_DECAY {
+            ppu.tick();
+        }
+        assert_eq!(ppu.read(0x2005, &mut None), 0x00);
+    }
+}